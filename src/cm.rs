@@ -1,12 +1,19 @@
-use ctype::{isalpha, isdigit, isspace};
+use ctype::{isalnum, isalpha, isdigit, ispunct, isspace};
 use nodes;
 use nodes::TableAlignment;
-use nodes::{AstNode, ListDelimType, ListType, NodeLink, NodeValue};
-use parser::ComrakOptions;
+use nodes::{AstNode, ListDelimType, ListType, NodeContainer, NodeLink, NodeValue};
+use parser::{
+    is_replaceable, CodeFenceChar, ComrakOptions, EmphasisDelimiter, HeadingStyle,
+    ListStyleType, OrderedListNumbering,
+};
 use scanners;
 use std;
 use std::cmp::max;
 use std::io::{self, Write};
+use std::str;
+use strings::is_cjk;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 /// Formats an AST as CommonMark, modified by the given options.
 pub fn format_document<'a>(
@@ -36,7 +43,6 @@ struct CommonMarkFormatter<'a, 'o> {
     no_linebreaks: bool,
     in_tight_list_item: bool,
     custom_escape: Option<fn(&'a AstNode<'a>, u8) -> bool>,
-    footnote_ix: u32,
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -73,7 +79,6 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
             no_linebreaks: false,
             in_tight_list_item: false,
             custom_escape: None,
-            footnote_ix: 0,
         }
     }
 
@@ -101,12 +106,17 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         }
 
         let mut i = 0;
+        let mut replacement_escape = None;
         while i < buf.len() {
             if self.begin_line {
                 self.v.extend(&self.prefix);
                 self.column = self.prefix.len();
             }
 
+            if escaping == Escaping::Normal && replacement_escape.map_or(true, |e| e < i) {
+                replacement_escape = self.replacement_escape(buf, i);
+            }
+
             if self.custom_escape.is_some() && self.custom_escape.unwrap()(self.node, buf[i]) {
                 self.v.push(b'\\');
             }
@@ -132,6 +142,23 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 self.begin_line = true;
                 self.begin_content = true;
                 self.last_breakable = 0;
+            } else if buf[i] >= 0x80 {
+                // Breaking a code span between CJK characters would add a space to it.
+                let wrap = wrap && escaping == Escaping::Normal;
+                i += self.output_grapheme(&buf[i..], wrap) - 1;
+                self.begin_line = false;
+                self.begin_content = false;
+            } else if replacement_escape == Some(i) {
+                if ispunct(buf[i]) {
+                    write!(self.v, "\\{}", buf[i] as char).unwrap();
+                    self.column += 2;
+                } else {
+                    let entity = format!("&#{};", buf[i]);
+                    self.v.extend_from_slice(entity.as_bytes());
+                    self.column += entity.len();
+                }
+                self.begin_line = false;
+                self.begin_content = false;
             } else if escaping == Escaping::Literal {
                 self.v.push(buf[i]);
                 self.column += 1;
@@ -148,12 +175,14 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 && !self.begin_line
                 && self.last_breakable > 0
             {
-                let remainder = self.v[self.last_breakable + 1..].to_vec();
+                // Breaks at a space drop the space; breaks between CJK characters drop nothing.
+                let skip = if self.v[self.last_breakable] == b' ' { 1 } else { 0 };
+                let remainder = self.v[self.last_breakable + skip..].to_vec();
                 self.v.truncate(self.last_breakable);
                 self.v.push(b'\n');
                 self.v.extend(&self.prefix);
                 self.v.extend(&remainder);
-                self.column = self.prefix.len() + remainder.len();
+                self.column = self.prefix.len() + display_width(&remainder);
                 self.last_breakable = 0;
                 self.begin_line = false;
                 self.begin_content = false;
@@ -163,6 +192,90 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         }
     }
 
+    /// The index of the byte to escape in `buf` if the text at `i` would be parsed as a
+    /// typographic replacement: the first ASCII punctuation character of the replacement, or
+    /// else its first ASCII character, which is written as a character reference.  Word
+    /// boundaries are checked as by the parser.
+    fn replacement_escape(&self, buf: &[u8], i: usize) -> Option<usize> {
+        let in_word = |c: u8| isalnum(c) || c == b'/';
+        let prev = if i > 0 {
+            Some(buf[i - 1])
+        } else {
+            self.v.last().cloned()
+        };
+
+        for replacement in &self.options.ext_replacements {
+            let original = replacement.0.as_bytes();
+            if !is_replaceable(original) || !buf[i..].starts_with(original) {
+                continue;
+            }
+            let end = i + original.len();
+            if isalnum(original[0]) && prev.map_or(false, &in_word) {
+                continue;
+            }
+            if isalnum(original[original.len() - 1]) && buf.get(end).map_or(false, |&c| in_word(c))
+            {
+                continue;
+            }
+
+            return original
+                .iter()
+                .position(|&c| c < 0x80 && ispunct(c))
+                .or_else(|| original.iter().position(|&c| c < 0x80))
+                .map(|p| i + p);
+        }
+        None
+    }
+
+    /// Outputs the grapheme cluster at the start of `buf`, which starts with a non-ASCII byte,
+    /// returning its length in bytes.  The column advances by its display width, and if
+    /// wrapping, a break opportunity is recorded before it where CJK text permits one.
+    fn output_grapheme(&mut self, buf: &[u8], wrap: bool) -> usize {
+        // Grapheme clusters are short; don't validate the rest of the buffer each time.
+        let window = &buf[..std::cmp::min(buf.len(), 64)];
+        let valid = match str::from_utf8(window) {
+            Ok(s) => s,
+            Err(e) => str::from_utf8(&window[..e.valid_up_to()]).unwrap(),
+        };
+
+        let grapheme = match valid.graphemes(true).next() {
+            Some(grapheme) => grapheme,
+            None => {
+                self.v.push(buf[0]);
+                self.column += 1;
+                return 1;
+            }
+        };
+
+        let c = grapheme.chars().next().unwrap();
+        if wrap && !self.begin_line {
+            if let Some(prev) = self.last_char() {
+                if !prev.is_ascii()
+                    && (is_cjk(prev) || is_cjk(c))
+                    && !is_cjk_no_break_before(c)
+                    && !is_cjk_no_break_after(prev)
+                {
+                    self.last_breakable = self.v.len();
+                }
+            }
+        }
+
+        self.v.extend_from_slice(grapheme.as_bytes());
+        self.column += UnicodeWidthStr::width(grapheme);
+        grapheme.len()
+    }
+
+    fn last_char(&self) -> Option<char> {
+        let start = self
+            .v
+            .iter()
+            .rposition(|&b| b & 0xc0 != 0x80)
+            .unwrap_or(0);
+        str::from_utf8(&self.v[start..])
+            .ok()
+            .and_then(|s| s.chars().next())
+    }
+
     fn outc(&mut self, c: u8, escaping: Escaping, nextc: Option<&u8>) {
         let follows_digit = !self.v.is_empty() && isdigit(self.v[self.v.len() - 1]);
 
@@ -181,6 +294,10 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     || c == b'`'
                     || c == b'!'
                     || (c == b'&' && isalpha(nextc))
+                    || (c == b'~'
+                        && (self.options.ext_strikethrough || self.options.ext_subscript))
+                    || (c == b'=' && nextc == b'=' && self.options.ext_highlight)
+                    || (c == b'+' && nextc == b'+' && self.options.ext_insert)
                     || (c == b'!' && nextc == 0x5b)
                     || (self.begin_content
                         && (c == b'-' || c == b'+' || c == b'=')
@@ -246,6 +363,132 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         }
     }
 
+    fn format_aligned_table(&mut self, node: &'a AstNode<'a>) {
+        let alignments = match node.data.borrow().value {
+            NodeValue::Table(ref alignments) => alignments.clone(),
+            _ => unreachable!(),
+        };
+
+        // Each cell's contents are rendered up front so the column widths are known before
+        // anything is written.
+        let mut widths = vec![3; alignments.len()];
+        let mut rows = vec![];
+        for row in node.children() {
+            let mut cells = vec![];
+            for (i, cell) in row.children().enumerate() {
+                let mut f = CommonMarkFormatter::new(cell, self.options);
+                f.custom_escape = Some(table_escape);
+                f.no_linebreaks = true;
+                f.begin_content = false;
+                for ch in cell.children() {
+                    f.format(ch);
+                }
+                let width = display_width(&f.v);
+                widths[i] = max(widths[i], width);
+                cells.push((f.v, width));
+            }
+            let header = match row.data.borrow().value {
+                NodeValue::TableRow(header) => header,
+                _ => unreachable!(),
+            };
+            rows.push((header, cells));
+        }
+
+        for (header, cells) in rows {
+            self.cr();
+            write!(self, "|").unwrap();
+            for (i, (content, width)) in cells.into_iter().enumerate() {
+                let padding = widths[i] - width;
+                let (before, after) = match alignments[i] {
+                    TableAlignment::Right => (padding, 0),
+                    TableAlignment::Center => (padding / 2, padding - padding / 2),
+                    TableAlignment::Left | TableAlignment::None => (0, padding),
+                };
+                write!(self, " {:1$}", "", before).unwrap();
+                self.write_all(&content).unwrap();
+                write!(self, "{:1$} |", "", after).unwrap();
+            }
+
+            if header {
+                self.cr();
+                write!(self, "|").unwrap();
+                for (i, a) in alignments.iter().enumerate() {
+                    write!(self, " ").unwrap();
+                    self.write_all(&delimiter_row_cell(*a, widths[i])).unwrap();
+                    write!(self, " |").unwrap();
+                }
+            }
+        }
+        self.cr();
+    }
+
+    /// Writes a definition for each abbreviation marked in the document, so that they're marked
+    /// again when it's parsed.
+    fn format_abbreviation_definitions(&mut self, root: &'a AstNode<'a>) {
+        let mut definitions: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        for node in root.descendants() {
+            if let NodeValue::Abbreviation(ref na) = node.data.borrow().value {
+                let mut text = vec![];
+                for child in node.children() {
+                    if let NodeValue::Text(ref literal) = child.data.borrow().value {
+                        text.extend_from_slice(literal);
+                    }
+                }
+                if !definitions.iter().any(|definition| definition.0 == text) {
+                    definitions.push((text, na.title.clone()));
+                }
+            }
+        }
+
+        if definitions.is_empty() {
+            return;
+        }
+        self.blankline();
+        for definition in definitions {
+            write!(self, "*[").unwrap();
+            self.write_all(&definition.0).unwrap();
+            write!(self, "]: ").unwrap();
+            self.write_all(&definition.1).unwrap();
+            self.cr();
+        }
+    }
+
+    /// Writes the name, attributes and title of a container after its opening fence.
+    fn format_container_info(&mut self, nc: &NodeContainer) {
+        if !nc.name.is_empty() {
+            write!(self, " ").unwrap();
+            self.write_all(&nc.name).unwrap();
+        }
+        if !nc.attributes.is_empty() {
+            write!(self, " {{").unwrap();
+            for (i, attribute) in nc.attributes.iter().enumerate() {
+                if i > 0 {
+                    write!(self, " ").unwrap();
+                }
+                let (key, value) = (&attribute.0[..], &attribute.1[..]);
+                if key == b"id" {
+                    write!(self, "#").unwrap();
+                    self.write_all(value).unwrap();
+                } else if key == b"class" {
+                    write!(self, ".").unwrap();
+                    self.write_all(value).unwrap();
+                } else if value.is_empty() {
+                    self.write_all(key).unwrap();
+                } else {
+                    self.write_all(key).unwrap();
+                    write!(self, "=\"").unwrap();
+                    self.write_all(value).unwrap();
+                    write!(self, "\"").unwrap();
+                }
+            }
+            write!(self, "}}").unwrap();
+        }
+        if !nc.title.is_empty() {
+            write!(self, " ").unwrap();
+            self.write_all(&nc.title).unwrap();
+        }
+    }
+
     fn get_in_tight_list_item(&self, node: &'a AstNode<'a>) -> bool {
         let tmp = match nodes::containing_block(node) {
             Some(tmp) => tmp,
@@ -286,7 +529,9 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         }
 
         match node.data.borrow().value {
-            NodeValue::Document => (),
+            NodeValue::Document => if !entering {
+                self.format_abbreviation_definitions(node);
+            },
             NodeValue::BlockQuote => if entering {
                 write!(self, "> ").unwrap();
                 self.begin_content = true;
@@ -296,6 +541,40 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 self.prefix.truncate(new_len);
                 self.blankline();
             },
+            NodeValue::SpoilerBlock => if entering {
+                write!(self, ">! ").unwrap();
+                self.begin_content = true;
+                write!(self.prefix, ">! ").unwrap();
+            } else {
+                let new_len = self.prefix.len() - 3;
+                self.prefix.truncate(new_len);
+                self.blankline();
+            },
+            NodeValue::Container(ref nc) => {
+                // As the first child of a block quote or list item, the fence goes on the
+                // marker's line.
+                let first_in_container = entering
+                    && node.previous_sibling().is_none()
+                    && match node.parent() {
+                        Some(parent) => match parent.data.borrow().value {
+                            NodeValue::BlockQuote
+                            | NodeValue::SpoilerBlock
+                            | NodeValue::Item(..)
+                            | NodeValue::DescriptionDetails => true,
+                            _ => false,
+                        },
+                        _ => false,
+                    };
+
+                if !first_in_container {
+                    self.blankline();
+                }
+                self.write_all(&vec![b':'; max(3, nc.fence_length)]).unwrap();
+                if entering {
+                    self.format_container_info(nc);
+                }
+                self.blankline();
+            }
             NodeValue::List(..) => if !entering && match node.next_sibling() {
                 Some(next_sibling) => match next_sibling.data.borrow().value {
                     NodeValue::CodeBlock(..) | NodeValue::List(..) => true,
@@ -320,10 +599,12 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 } else {
                     let mut list_number = parent.start;
                     let list_delim = parent.delimiter;
-                    let mut tmpch = node;
-                    while let Some(tmp) = tmpch.previous_sibling() {
-                        tmpch = tmp;
-                        list_number += 1;
+                    if self.options.ordered_list_numbering == OrderedListNumbering::Sequential {
+                        let mut tmpch = node;
+                        while let Some(tmp) = tmpch.previous_sibling() {
+                            tmpch = tmp;
+                            list_number += 1;
+                        }
                     }
                     write!(
                         listmarker,
@@ -341,7 +622,12 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
 
                 if entering {
                     if parent.list_type == ListType::Bullet {
-                        write!(self, "  - ").unwrap();
+                        let bullet = match self.options.list_style {
+                            ListStyleType::Dash => '-',
+                            ListStyleType::Plus => '+',
+                            ListStyleType::Star => '*',
+                        };
+                        write!(self, "  {} ", bullet).unwrap();
                     } else {
                         self.write_all(&listmarker).unwrap();
                     }
@@ -361,17 +647,31 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
             NodeValue::DescriptionDetails => if entering {
                 write!(self, ": ").unwrap()
             },
-            NodeValue::Heading(ref nch) => if entering {
-                for _ in 0..nch.level {
-                    write!(self, "#").unwrap();
+            NodeValue::Heading(ref nch) => {
+                let setext = self.options.heading_style == HeadingStyle::Setext
+                    && nch.level <= 2
+                    && node.first_child().is_some();
+
+                if entering {
+                    if !setext {
+                        for _ in 0..nch.level {
+                            write!(self, "#").unwrap();
+                        }
+                        write!(self, " ").unwrap();
+                    }
+                    self.begin_content = true;
+                    self.no_linebreaks = true;
+                } else {
+                    self.no_linebreaks = false;
+                    if setext {
+                        let underline_length = max(3, self.column - self.prefix.len());
+                        let underline = if nch.level == 1 { b'=' } else { b'-' };
+                        self.cr();
+                        self.write_all(&vec![underline; underline_length]).unwrap();
+                    }
+                    self.blankline();
                 }
-                write!(self, " ").unwrap();
-                self.begin_content = true;
-                self.no_linebreaks = true;
-            } else {
-                self.no_linebreaks = false;
-                self.blankline();
-            },
+            }
             NodeValue::CodeBlock(ref ncb) => if entering {
                 let first_in_list_item = node.previous_sibling().is_none() && match node.parent() {
                     Some(parent) => match parent.data.borrow().value {
@@ -397,10 +697,18 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     let new_len = self.prefix.len() - 4;
                     self.prefix.truncate(new_len);
                 } else {
-                    let numticks = max(3, longest_backtick_sequence(&ncb.literal) + 1);
-                    for _ in 0..numticks {
-                        write!(self, "`").unwrap();
-                    }
+                    let fence_char = match self.options.code_fence_char {
+                        CodeFenceChar::Backtick if !ncb.info.contains(&b'`') => b'`',
+                        _ => b'~',
+                    };
+                    let fence = vec![
+                        fence_char;
+                        max(
+                            max(3, self.options.code_fence_length),
+                            longest_char_sequence(&ncb.literal, fence_char) + 1,
+                        )
+                    ];
+                    self.write_all(&fence).unwrap();
                     if !ncb.info.is_empty() {
                         write!(self, " ").unwrap();
                         self.write_all(&ncb.info).unwrap();
@@ -408,9 +716,7 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     self.cr();
                     self.write_all(&ncb.literal).unwrap();
                     self.cr();
-                    for _ in 0..numticks {
-                        write!(self, "`").unwrap();
-                    }
+                    self.write_all(&fence).unwrap();
                 }
                 self.blankline();
             },
@@ -421,7 +727,10 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
             },
             NodeValue::ThematicBreak => if entering {
                 self.blankline();
-                write!(self, "-----").unwrap();
+                match self.options.thematic_break {
+                    Some(ref tb) if is_thematic_break(tb) => self.write_all(tb.as_bytes()).unwrap(),
+                    _ => write!(self, "-----").unwrap(),
+                }
                 self.blankline();
             },
             NodeValue::Paragraph => if !entering {
@@ -465,38 +774,47 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
             NodeValue::HtmlInline(ref literal) => if entering {
                 self.write_all(literal).unwrap();
             },
-            NodeValue::Strong => if entering {
-                write!(self, "**").unwrap();
-            } else {
-                write!(self, "**").unwrap();
-            },
-            NodeValue::Emph => {
-                let emph_delim = if match node.parent() {
-                    Some(parent) => match parent.data.borrow().value {
-                        NodeValue::Emph => true,
-                        _ => false,
-                    },
-                    _ => false,
-                } && node.next_sibling().is_none()
-                    && node.previous_sibling().is_none()
-                {
-                    b'_'
-                } else {
+            NodeValue::Strong => {
+                // With the underline extension, `__` is underline rather than strong emphasis.
+                let strong_delim = if self.options.ext_underline {
                     b'*'
+                } else {
+                    delimiter_char(node, self.options.strong_delimiter)
                 };
-
+                self.write_all(&[strong_delim, strong_delim]).unwrap();
+            }
+            NodeValue::Emph => {
+                let emph_delim = emph_delimiter(node, self.options.emph_delimiter);
                 self.write_all(&[emph_delim]).unwrap();
             }
-            NodeValue::Strikethrough => if entering {
-                write!(self, "~").unwrap();
-            } else {
-                write!(self, "~").unwrap();
-            },
+            NodeValue::Strikethrough => {
+                // With the subscript extension, `~` is subscript rather than strikethrough.
+                if self.options.ext_subscript {
+                    write!(self, "~~").unwrap();
+                } else {
+                    write!(self, "~").unwrap();
+                }
+            }
             NodeValue::Superscript => if entering {
                 write!(self, "^").unwrap();
             } else {
                 write!(self, "^").unwrap();
             },
+            NodeValue::Subscript => {
+                write!(self, "~").unwrap();
+            }
+            NodeValue::Highlight => {
+                write!(self, "==").unwrap();
+            }
+            NodeValue::Insert => {
+                write!(self, "++").unwrap();
+            }
+            NodeValue::Underline => {
+                write!(self, "__").unwrap();
+            }
+            NodeValue::Spoiler => {
+                write!(self, "||").unwrap();
+            }
             NodeValue::Link(ref nl) => if is_autolink(node, nl) {
                 if entering {
                     write!(self, "<").unwrap();
@@ -533,6 +851,13 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 write!(self, ")").unwrap();
             },
             NodeValue::Table(..) => {
+                if entering && self.options.align_tables {
+                    self.blankline();
+                    self.format_aligned_table(node);
+                    self.blankline();
+                    return false;
+                }
+
                 if entering {
                     self.custom_escape = Some(table_escape);
                 } else {
@@ -579,30 +904,89 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     self.cr();
                 }
             },
-            NodeValue::FootnoteDefinition(_) => if entering {
-                self.footnote_ix += 1;
-                let footnote_ix = self.footnote_ix;
-                write!(self, "[^{}]:\n", footnote_ix).unwrap();
+            NodeValue::FootnoteDefinition(ref nfd) => if entering {
+                self.write_all(b"[^").unwrap();
+                self.write_all(&nfd.name).unwrap();
+                self.write_all(b"]:\n").unwrap();
                 write!(self.prefix, "    ").unwrap();
             } else {
                 let new_len = self.prefix.len() - 4;
                 self.prefix.truncate(new_len);
             },
-            NodeValue::FootnoteReference(ref r) => if entering {
+            NodeValue::FootnoteReference(ref nfr) => if entering {
                 self.write_all(b"[^").unwrap();
-                self.write_all(r).unwrap();
+                self.write_all(&nfr.name).unwrap();
                 self.write_all(b"]").unwrap();
             },
+            NodeValue::TaskItem(ref nti) => if entering {
+                // The symbol is kept where it agrees with `checked`, which may have been changed.
+                let symbol = match (nti.checked, nti.symbol) {
+                    (true, b' ') => b'x',
+                    (true, symbol) => symbol,
+                    (false, _) => b' ',
+                };
+                self.write_all(&[b'[', symbol, b']']).unwrap();
+            },
+            NodeValue::Replacement(ref nr) => if entering {
+                // The original text was parsed as a replacement rather than as any other
+                // syntax, so it needs no escaping to be parsed as one again.
+                if self.options.ext_replacements_original {
+                    self.output(&nr.original, allow_wrap, Escaping::Literal);
+                } else {
+                    self.output(&nr.replacement, allow_wrap, Escaping::Normal);
+                }
+            },
+            NodeValue::Abbreviation(..) => (),
         };
         true
     }
 }
 
-fn longest_backtick_sequence(literal: &[u8]) -> usize {
+fn delimiter_row_cell(alignment: TableAlignment, width: usize) -> Vec<u8> {
+    let mut cell = vec![b'-'; width];
+    match alignment {
+        TableAlignment::Left => cell[0] = b':',
+        TableAlignment::Center => {
+            cell[0] = b':';
+            cell[width - 1] = b':';
+        }
+        TableAlignment::Right => cell[width - 1] = b':',
+        TableAlignment::None => (),
+    }
+    cell
+}
+
+/// Closing punctuation and small kana, which must not begin a line.
+fn is_cjk_no_break_before(c: char) -> bool {
+    match c {
+        '、' | '。' | '，' | '．' | '・' | '：' | '；' | '？' | '！' | 'ー' | '）' | '］' | '｝'
+        | '」' | '』' | '】' | '〕' | '〉' | '》' | '〗' | '〙' | '々' | '〜' | 'ぁ' | 'ぃ'
+        | 'ぅ' | 'ぇ' | 'ぉ' | 'っ' | 'ゃ' | 'ゅ' | 'ょ' | 'ゎ' | 'ァ' | 'ィ' | 'ゥ' | 'ェ'
+        | 'ォ' | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' | 'ヵ' | 'ヶ' => true,
+        _ => false,
+    }
+}
+
+/// Opening punctuation, which must not end a line.
+fn is_cjk_no_break_after(c: char) -> bool {
+    match c {
+        '（' | '［' | '｛' | '「' | '『' | '【' | '〔' | '〈' | '《' | '〖' | '〘' => true,
+        _ => false,
+    }
+}
+
+fn display_width(s: &[u8]) -> usize {
+    match str::from_utf8(s) {
+        Ok(s) => UnicodeWidthStr::width(s),
+        Err(_) => s.len(),
+    }
+}
+
+fn longest_char_sequence(literal: &[u8], ch: u8) -> usize {
     let mut longest = 0;
     let mut current = 0;
     for c in literal {
-        if *c == b'`' {
+        if *c == ch {
             current += 1;
         } else {
             if current > longest {
@@ -643,6 +1027,58 @@ fn shortest_unused_sequence(literal: &[u8], f: u8) -> usize {
     i
 }
 
+fn emph_delimiter<'a>(node: &'a AstNode<'a>, preferred: EmphasisDelimiter) -> u8 {
+    // Emphasis directly inside emphasis with no siblings must alternate delimiters, or the
+    // two would be read back as strong emphasis.
+    let mut outermost = node;
+    let mut alternate = false;
+    while let Some(parent) = outermost.parent() {
+        let nested = match parent.data.borrow().value {
+            NodeValue::Emph => {
+                outermost.previous_sibling().is_none() && outermost.next_sibling().is_none()
+            }
+            _ => false,
+        };
+        if !nested {
+            break;
+        }
+        outermost = parent;
+        alternate = !alternate;
+    }
+
+    match (delimiter_char(outermost, preferred), alternate) {
+        (c, false) => c,
+        (b'*', true) => b'_',
+        (_, true) => b'*',
+    }
+}
+
+fn delimiter_char<'a>(node: &'a AstNode<'a>, preferred: EmphasisDelimiter) -> u8 {
+    match preferred {
+        EmphasisDelimiter::Underscore if !is_intraword(node) => b'_',
+        _ => b'*',
+    }
+}
+
+fn is_intraword<'a>(node: &'a AstNode<'a>) -> bool {
+    let word_char = |c: Option<&u8>| c.map_or(false, |&c| !isspace(c) && !ispunct(c));
+    let before = node.previous_sibling().map_or(false, |n| match n.data.borrow().value {
+        NodeValue::Text(ref t) => word_char(t.last()),
+        _ => false,
+    });
+    let after = node.next_sibling().map_or(false, |n| match n.data.borrow().value {
+        NodeValue::Text(ref t) => word_char(t.first()),
+        _ => false,
+    });
+    before || after
+}
+
+fn is_thematic_break(s: &str) -> bool {
+    let mut line = s.as_bytes().to_vec();
+    line.push(b'\n');
+    scanners::thematic_break(&line) == Some(line.len())
+}
+
 fn is_autolink<'a>(node: &'a AstNode<'a>, nl: &NodeLink) -> bool {
     if nl.url.is_empty() || scanners::scheme(&nl.url).is_none() {
         return false;
@@ -1,7 +1,8 @@
-use ctype::isspace;
-use nodes::{AstNode, ListType, NodeValue, TableAlignment};
+use ctype::{isalnum, isspace};
+use nodes::{AstNode, ListType, NodeContainer, NodeValue, TableAlignment};
 use parser::ComrakOptions;
 use regex::Regex;
+use sanitize::{attribute_allowed, sanitize_html};
 use scanners;
 use std::borrow::Cow;
 use std::cell::Cell;
@@ -22,11 +23,78 @@ pub fn format_document<'a>(
     let mut f = HtmlFormatter::new(options, &mut writer);
     f.format(root, false)?;
     if f.footnote_ix > 0 {
-        f.output.write_all(b"</ol>\n</section>\n")?;
+        f.output.write_all(b"</ol>\n")?;
+        let end = &options.ext_footnote_html.section_end;
+        if !end.is_empty() {
+            f.output.write_all(end.as_bytes())?;
+            f.output.write_all(b"\n")?;
+        }
     }
     Ok(())
 }
 
+/// How footnotes are written in HTML, set with the `ext_footnote_html` option.
+///
+/// The default writes footnotes as `cmark-gfm` does.
+///
+/// ```
+/// # use comrak::{markdown_to_html, ComrakOptions, FootnoteHtml};
+/// let options = ComrakOptions {
+///   ext_footnotes: true,
+///   ext_footnote_html: FootnoteHtml {
+///     id_prefix: "post-7-".to_string(),
+///     section_start: "<div class=\"notes\" role=\"doc-endnotes\">".to_string(),
+///     section_end: "</div>".to_string(),
+///     backref_content: "Back".to_string(),
+///     ref_attributes: "role=\"doc-noteref\"".to_string(),
+///     backref_attributes: "aria-label=\"Back to reference {}\"".to_string(),
+///   },
+///   ..ComrakOptions::default()
+/// };
+/// assert_eq!(markdown_to_html("Hi[^x].\n\n[^x]: A greeting.\n", &options),
+///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#post-7-fn1\" id=\"post-7-fnref1\" \
+///             role=\"doc-noteref\">1</a></sup>.</p>\n\
+///             <div class=\"notes\" role=\"doc-endnotes\">\n<ol>\n<li id=\"post-7-fn1\">\n\
+///             <p>A greeting. <a href=\"#post-7-fnref1\" class=\"footnote-backref\" \
+///             aria-label=\"Back to reference 1\">Back</a></p>\n</li>\n</ol>\n</div>\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FootnoteHtml {
+    /// A prefix for the IDs of footnotes and references, which are otherwise `fn1`, `fnref1`,
+    /// and so on, such as to keep them unique when several documents are shown on one page.
+    pub id_prefix: String,
+
+    /// Raw HTML written before the list of footnotes.  Nothing is written if empty.
+    pub section_start: String,
+
+    /// Raw HTML written after the list of footnotes.  Nothing is written if empty.
+    pub section_end: String,
+
+    /// Raw HTML for the content of each link back to a reference.
+    pub backref_content: String,
+
+    /// Raw HTML attributes added to the link of each reference, such as `role="doc-noteref"`.
+    pub ref_attributes: String,
+
+    /// Raw HTML attributes added to each link back to a reference.  `{}` is replaced by the
+    /// number of the reference, such as `1` or, for a second reference to the same footnote,
+    /// `1-2`.
+    pub backref_attributes: String,
+}
+
+impl Default for FootnoteHtml {
+    fn default() -> FootnoteHtml {
+        FootnoteHtml {
+            id_prefix: String::new(),
+            section_start: "<section class=\"footnotes\">".to_string(),
+            section_end: "</section>".to_string(),
+            backref_content: "↩".to_string(),
+            ref_attributes: String::new(),
+            backref_attributes: String::new(),
+        }
+    }
+}
+
 pub struct WriteWithLast<'w> {
     output: &'w mut Write,
     pub last_was_lf: Cell<bool>,
@@ -51,7 +119,9 @@ struct HtmlFormatter<'o> {
     options: &'o ComrakOptions,
     seen_anchors: HashSet<String>,
     footnote_ix: u32,
+    footnote_references: u32,
     written_footnote_ix: u32,
+    task_ix: usize,
 }
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
@@ -170,7 +240,9 @@ impl<'o> HtmlFormatter<'o> {
             output: output,
             seen_anchors: HashSet::new(),
             footnote_ix: 0,
+            footnote_references: 0,
             written_footnote_ix: 0,
+            task_ix: 0,
         }
     }
 
@@ -268,6 +340,105 @@ impl<'o> HtmlFormatter<'o> {
         Ok(())
     }
 
+    /// Writes the opening tag of a container: a `<div>` with the container's name and any
+    /// classes given as its class, followed by its other attributes.
+    fn container_start(&mut self, nc: &NodeContainer) -> io::Result<()> {
+        let mut classes = vec![];
+        if !nc.name.is_empty() {
+            classes.push(&nc.name[..]);
+        }
+        for attribute in &nc.attributes {
+            if attribute.0 == b"class" && !attribute.1.is_empty() {
+                classes.push(&attribute.1[..]);
+            }
+        }
+
+        self.output.write_all(b"<div")?;
+        if !classes.is_empty() {
+            self.output.write_all(b" class=\"")?;
+            self.escape(&classes.join(&b' '))?;
+            self.output.write_all(b"\"")?;
+        }
+        for attribute in &nc.attributes {
+            if attribute.0 != b"class" && self.container_attribute_allowed(attribute) {
+                self.output.write_all(b" ")?;
+                self.escape(&attribute.0)?;
+                self.output.write_all(b"=\"")?;
+                self.escape(&attribute.1)?;
+                self.output.write_all(b"\"")?;
+            }
+        }
+        self.output.write_all(b">\n")
+    }
+
+    /// Whether a container attribute other than `class` may be written.  Its name must be a
+    /// valid attribute name, and it must be allowed on a `<div>` by the `sanitize` policy if
+    /// set.  With the `safe` option, event handlers and styles are omitted.
+    fn container_attribute_allowed(&self, attribute: &(Vec<u8>, Vec<u8>)) -> bool {
+        let valid = |c: &u8| isalnum(*c) || *c == b'_' || *c == b':' || *c == b'-';
+        if attribute.0.is_empty() || !attribute.0.iter().all(valid) {
+            return false;
+        }
+
+        let name = String::from_utf8_lossy(&attribute.0).to_lowercase();
+        if let Some(ref policy) = self.options.sanitize {
+            let allowed = match policy.tags.get("div") {
+                Some(allowed) => allowed,
+                None => return false,
+            };
+            attribute_allowed(policy, allowed, &name, Some(&attribute.1))
+        } else if self.options.safe {
+            !name.starts_with("on") && name != "style"
+        } else {
+            true
+        }
+    }
+
+    /// The URL of a link or image, as rewritten by the `url_rewriter` option, if set.
+    fn rewrite_url<'a, 'u>(&self, node: &'a AstNode<'a>, url: &'u [u8]) -> Cow<'u, [u8]> {
+        match self.options.url_rewriter {
+            Some(ref rewriter) => {
+                Cow::Owned(rewriter(node, &String::from_utf8_lossy(url)).into_bytes())
+            }
+            None => Cow::Borrowed(url),
+        }
+    }
+
+    /// The URL to output for a link or image, after applying the URL policy, if any.
+    fn policy_url<'u>(&self, url: &'u [u8], image: bool) -> Cow<'u, [u8]> {
+        let policy = match self.options.url_policy {
+            Some(ref policy) => policy,
+            None => return Cow::Borrowed(url),
+        };
+
+        let url = String::from_utf8_lossy(url);
+        let url = if image {
+            policy.image_url(&url)
+        } else {
+            policy.link_url(&url)
+        };
+        Cow::Owned(url.map_or(vec![], |u| u.into_bytes()))
+    }
+
+    fn put_external_link_attributes(&mut self, url: &[u8]) -> io::Result<()> {
+        let policy = match self.options.url_policy {
+            Some(ref policy) if policy.is_external(&String::from_utf8_lossy(url)) => policy,
+            _ => return Ok(()),
+        };
+
+        if let Some(ref rel) = policy.external_link_rel {
+            self.output.write_all(b" rel=\"")?;
+            self.escape(rel.as_bytes())?;
+            self.output.write_all(b"\"")?;
+        }
+        if let Some(ref target) = policy.external_link_target {
+            self.output.write_all(b" target=\"")?;
+            self.escape(target.as_bytes())?;
+            self.output.write_all(b"\"")?;
+        }
+        Ok(())
+    }
+
     fn format<'a>(&mut self, node: &'a AstNode<'a>, plain: bool) -> io::Result<()> {
         // Traverse the AST iteratively using a work stack, with pre- and
         // post-child-traversal phases. During pre-order traversal render the
@@ -284,6 +455,12 @@ impl<'o> HtmlFormatter<'o> {
         while let Some((node, plain, phase)) = stack.pop() {
             match phase {
                 Phase::Pre => {
+                    if let NodeValue::FootnoteDefinition(ref nfd) = node.data.borrow().value {
+                        if nfd.total_references == 0 {
+                            continue;
+                        }
+                    }
+
                     let new_plain;
                     if plain {
                         match node.data.borrow().value {
@@ -292,6 +469,9 @@ impl<'o> HtmlFormatter<'o> {
                             | NodeValue::HtmlInline(ref literal) => {
                                 self.escape(literal)?;
                             }
+                            NodeValue::Replacement(ref nr) => {
+                                self.escape(&nr.replacement)?;
+                            }
                             NodeValue::LineBreak | NodeValue::SoftBreak => {
                                 self.output.write_all(b" ")?;
                             }
@@ -322,6 +502,7 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::Text(ref literal) | NodeValue::Code(ref literal) => {
                 output.extend_from_slice(literal)
             }
+            NodeValue::Replacement(ref nr) => output.extend_from_slice(&nr.replacement),
             NodeValue::LineBreak | NodeValue::SoftBreak => output.push(b' '),
             _ => for n in node.children() {
                 self.collect_text(n, output);
@@ -339,6 +520,21 @@ impl<'o> HtmlFormatter<'o> {
                 self.cr()?;
                 self.output.write_all(b"</blockquote>\n")?;
             },
+            NodeValue::SpoilerBlock => if entering {
+                self.cr()?;
+                self.output.write_all(b"<details class=\"spoiler\">\n")?;
+            } else {
+                self.cr()?;
+                self.output.write_all(b"</details>\n")?;
+            },
+            NodeValue::Container(ref nc) => {
+                self.cr()?;
+                match self.options.ext_container_html.as_ref().and_then(|f| f(nc, entering)) {
+                    Some(html) => self.output.write_all(html.as_bytes())?,
+                    None if entering => self.container_start(nc)?,
+                    None => self.output.write_all(b"</div>\n")?,
+                }
+            }
             NodeValue::List(ref nl) => if entering {
                 self.cr()?;
                 if nl.list_type == ListType::Bullet {
@@ -452,6 +648,8 @@ impl<'o> HtmlFormatter<'o> {
                 self.cr()?;
                 if self.options.safe {
                     self.output.write_all(b"<!-- raw HTML omitted -->")?;
+                } else if let Some(ref policy) = self.options.sanitize {
+                    self.output.write_all(&sanitize_html(&nhb.literal, policy))?;
                 } else if self.options.ext_tagfilter {
                     tagfilter_block(&nhb.literal, &mut self.output)?;
                 } else {
@@ -511,6 +709,8 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::HtmlInline(ref literal) => if entering {
                 if self.options.safe {
                     self.output.write_all(b"<!-- raw HTML omitted -->")?;
+                } else if let Some(ref policy) = self.options.sanitize {
+                    self.output.write_all(&sanitize_html(literal, policy))?;
                 } else if self.options.ext_tagfilter && tagfilter(literal) {
                     self.output.write_all(b"&lt;")?;
                     self.output.write_all(&literal[1..])?;
@@ -538,23 +738,54 @@ impl<'o> HtmlFormatter<'o> {
             } else {
                 self.output.write_all(b"</sup>")?;
             },
+            NodeValue::Subscript => if entering {
+                self.output.write_all(b"<sub>")?;
+            } else {
+                self.output.write_all(b"</sub>")?;
+            },
+            NodeValue::Highlight => if entering {
+                self.output.write_all(b"<mark>")?;
+            } else {
+                self.output.write_all(b"</mark>")?;
+            },
+            NodeValue::Insert => if entering {
+                self.output.write_all(b"<ins>")?;
+            } else {
+                self.output.write_all(b"</ins>")?;
+            },
+            NodeValue::Underline => if entering {
+                self.output.write_all(b"<u>")?;
+            } else {
+                self.output.write_all(b"</u>")?;
+            },
+            NodeValue::Spoiler => if entering {
+                self.output.write_all(b"<span class=\"spoiler\">")?;
+            } else {
+                self.output.write_all(b"</span>")?;
+            },
             NodeValue::Link(ref nl) => if entering {
                 self.output.write_all(b"<a href=\"")?;
-                if !self.options.safe || !dangerous_url(&nl.url) {
-                    self.escape_href(&nl.url)?;
+                let rewritten = self.rewrite_url(node, &nl.url);
+                let url = self.policy_url(&rewritten, false);
+                if !self.options.safe || !dangerous_url(&url) {
+                    self.escape_href(&url)?;
                 }
                 if !nl.title.is_empty() {
                     self.output.write_all(b"\" title=\"")?;
                     self.escape(&nl.title)?;
                 }
-                self.output.write_all(b"\">")?;
+                self.output.write_all(b"\"")?;
+                self.put_external_link_attributes(&rewritten)?;
+                self.output.write_all(b">")?;
             } else {
                 self.output.write_all(b"</a>")?;
             },
             NodeValue::Image(ref nl) => if entering {
                 self.output.write_all(b"<img src=\"")?;
-                if !self.options.safe || !dangerous_url(&nl.url) {
-                    self.escape_href(&nl.url)?;
+                let rewritten = self.rewrite_url(node, &nl.url);
+                let url = self.policy_url(&rewritten, true);
+                if !self.options.safe || !dangerous_url(&url) {
+                    self.escape_href(&url)?;
                 }
                 self.output.write_all(b"\" alt=\"")?;
                 return Ok(true);
@@ -646,26 +877,73 @@ impl<'o> HtmlFormatter<'o> {
                     self.output.write_all(b"</td>")?;
                 }
             }
-            NodeValue::FootnoteDefinition(_) => if entering {
+            NodeValue::FootnoteDefinition(ref nfd) => if entering {
+                let footnote_html = &self.options.ext_footnote_html;
                 if self.footnote_ix == 0 {
-                    self.output
-                        .write_all(b"<section class=\"footnotes\">\n<ol>\n")?;
+                    if !footnote_html.section_start.is_empty() {
+                        self.output
+                            .write_all(footnote_html.section_start.as_bytes())?;
+                        self.output.write_all(b"\n")?;
+                    }
+                    self.output.write_all(b"<ol>\n")?;
                 }
                 self.footnote_ix += 1;
-                write!(self.output, "<li id=\"fn{}\">\n", self.footnote_ix)?;
+                self.footnote_references = nfd.total_references;
+                write!(
+                    self.output,
+                    "<li id=\"{}fn{}\">\n",
+                    footnote_html.id_prefix, self.footnote_ix
+                )?;
             } else {
                 if self.put_footnote_backref()? {
                     self.output.write_all(b"\n")?;
                 }
                 self.output.write_all(b"</li>\n")?;
             },
-            NodeValue::FootnoteReference(ref r) => if entering {
-                let r = str::from_utf8(r).unwrap();
-                write!(
-                    self.output,
-                    "<sup class=\"footnote-ref\"><a href=\"#fn{}\" id=\"fnref{}\">{}</a></sup>",
-                    r, r, r
-                )?;
+            NodeValue::FootnoteReference(ref nfr) => if entering {
+                if nfr.ix == 0 {
+                    self.output.write_all(b"[^")?;
+                    self.escape(&nfr.name)?;
+                    self.output.write_all(b"]")?;
+                } else {
+                    let footnote_html = &self.options.ext_footnote_html;
+                    write!(
+                        self.output,
+                        "<sup class=\"footnote-ref\"><a href=\"#{}fn{}\" id=\"{}fnref{}{}\"",
+                        footnote_html.id_prefix,
+                        nfr.ix,
+                        footnote_html.id_prefix,
+                        nfr.ix,
+                        footnote_ref_suffix(nfr.ref_num)
+                    )?;
+                    if !footnote_html.ref_attributes.is_empty() {
+                        write!(self.output, " {}", footnote_html.ref_attributes)?;
+                    }
+                    write!(self.output, ">{}</a></sup>", nfr.ix)?;
+                }
+            },
+            NodeValue::TaskItem(ref nti) => if entering {
+                self.output.write_all(b"<input type=\"checkbox\"")?;
+                if self.options.ext_tasklist_interactive {
+                    write!(self.output, " data-task-index=\"{}\"", self.task_ix)?;
+                    self.task_ix += 1;
+                } else {
+                    self.output.write_all(b" disabled=\"\"")?;
+                }
+                if nti.checked {
+                    self.output.write_all(b" checked=\"\"")?;
+                }
+                self.output.write_all(b" />")?;
+            },
+            NodeValue::Replacement(ref nr) => if entering {
+                self.escape(&nr.replacement)?;
+            },
+            NodeValue::Abbreviation(ref na) => if entering {
+                self.output.write_all(b"<abbr title=\"")?;
+                self.escape(&na.title)?;
+                self.output.write_all(b"\">")?;
+            } else {
+                self.output.write_all(b"</abbr>")?;
             },
         }
         Ok(false)
@@ -677,11 +955,40 @@ impl<'o> HtmlFormatter<'o> {
         }
 
         self.written_footnote_ix = self.footnote_ix;
-        write!(
-            self.output,
-            "<a href=\"#fnref{}\" class=\"footnote-backref\">↩</a>",
-            self.footnote_ix
-        )?;
+        let footnote_html = &self.options.ext_footnote_html;
+        for ref_num in 1..self.footnote_references + 1 {
+            if ref_num > 1 {
+                self.output.write_all(b" ")?;
+            }
+            let number = format!("{}{}", self.footnote_ix, footnote_ref_suffix(ref_num));
+            write!(
+                self.output,
+                "<a href=\"#{}fnref{}\" class=\"footnote-backref\"",
+                footnote_html.id_prefix, number
+            )?;
+            if !footnote_html.backref_attributes.is_empty() {
+                write!(
+                    self.output,
+                    " {}",
+                    footnote_html.backref_attributes.replace("{}", &number)
+                )?;
+            }
+            write!(self.output, ">{}", footnote_html.backref_content)?;
+            if ref_num > 1 {
+                write!(self.output, "<sup class=\"footnote-ref\">{}</sup>", ref_num)?;
+            }
+            self.output.write_all(b"</a>")?;
+        }
         Ok(true)
     }
 }
+
+/// The suffix of the ID of each reference to a footnote after the first, such as `-2` in
+/// `fnref1-2`.
+fn footnote_ref_suffix(ref_num: u32) -> String {
+    if ref_num > 1 {
+        format!("-{}", ref_num)
+    } else {
+        String::new()
+    }
+}
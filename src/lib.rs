@@ -87,23 +87,42 @@ extern crate timebomb;
 extern crate twoway;
 extern crate typed_arena;
 extern crate unicode_categories;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
 mod arena_tree;
 mod cm;
 mod ctype;
 mod entity;
 mod html;
+pub mod lint;
+mod lossless;
 pub mod nodes;
 mod parser;
+pub mod query;
+mod sanitize;
 mod scanners;
+pub mod stats;
 mod strings;
+pub mod tasks;
 #[cfg(test)]
 mod tests;
+mod url_policy;
+pub mod visit;
 
 pub use cm::format_document as format_commonmark;
 pub use html::format_document as format_html;
-pub use parser::{parse_document, ComrakOptions};
+pub use html::FootnoteHtml;
+pub use lossless::{parse_document_lossless, LosslessDocument};
+pub use parser::{
+    parse_document, parse_document_with_references, parse_fragment, parse_inline,
+    typographic_replacements, AutolinkFilter, CodeFenceChar, ComrakOptions, ContainerHtml,
+    EmphasisDelimiter, HeadingStyle, ListStyleType, OrderedListNumbering, QuoteStyle,
+    ReferenceKind, ReferenceMap, ReferenceResolver, SmartPunctuation, Transform, UrlRewriter,
+};
+pub use sanitize::SanitizePolicy;
 pub use typed_arena::Arena;
+pub use url_policy::UrlPolicy;
 
 /// Render Markdown to HTML.
 ///
@@ -0,0 +1,564 @@
+//! A Markdown linter operating on the AST and the source it was parsed from.
+//!
+//! ```
+//! # use comrak::{parse_document, Arena, ComrakOptions};
+//! # use comrak::lint::Linter;
+//! let arena = Arena::new();
+//! let source = "# Title\n\n### Section\n\nSee https://example.com.\n";
+//! let root = parse_document(&arena, source, &ComrakOptions::default());
+//!
+//! let diagnostics = Linter::new(&ComrakOptions::default()).lint(root, source);
+//! assert_eq!(diagnostics.len(), 2);
+//! assert_eq!(diagnostics[0].rule, "heading-increment");
+//! assert_eq!(diagnostics[0].line, 3);
+//! assert_eq!(diagnostics[1].rule, "bare-url");
+//! assert_eq!(diagnostics[1].line, 5);
+//! ```
+
+use arena_tree::NodeEdge;
+use nodes::{self, AstNode, ListType, NodeValue};
+use parser::ComrakOptions;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use twoway::find_bytes;
+use unicode_width::UnicodeWidthStr;
+
+/// A problem found by a lint rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The name of the rule which produced this diagnostic.
+    pub rule: &'static str,
+
+    /// A description of the problem.
+    pub message: String,
+
+    /// The line in the source the problem occurs on, starting at 1.
+    pub line: u32,
+
+    /// The column in the source the problem occurs at, starting at 1, where known.
+    pub column: Option<usize>,
+}
+
+/// A lint rule.
+pub trait Rule {
+    /// The name of the rule, used in diagnostics and to disable it.
+    fn name(&self) -> &'static str;
+
+    /// Checks the document rooted at `root`, parsed from `source`, adding a diagnostic for each
+    /// problem found.
+    fn check<'a>(&self, root: &'a AstNode<'a>, source: &str, diagnostics: &mut Vec<Diagnostic>);
+}
+
+/// Runs a set of rules over a document.
+pub struct Linter {
+    rules: Vec<Box<Rule>>,
+}
+
+impl fmt::Debug for Linter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.rules.iter().map(|r| r.name()))
+            .finish()
+    }
+}
+
+impl Linter {
+    /// Creates a linter with all the built-in rules.  The `line-length` rule checks against
+    /// `options.width`, and is omitted if it is 0.
+    pub fn new(options: &ComrakOptions) -> Linter {
+        let mut linter = Linter::empty();
+        linter.add_rule(Box::new(HeadingIncrement));
+        linter.add_rule(Box::new(DuplicateHeading));
+        linter.add_rule(Box::new(BareUrl));
+        linter.add_rule(Box::new(EmptyLink));
+        linter.add_rule(Box::new(UnusedFootnote));
+        linter.add_rule(Box::new(MissingFootnote));
+        linter.add_rule(Box::new(ConsistentListMarker));
+        linter.add_rule(Box::new(TrailingSpaces));
+        if options.width > 0 {
+            linter.add_rule(Box::new(LineLength {
+                width: options.width,
+            }));
+        }
+        linter
+    }
+
+    /// Creates a linter with no rules.
+    pub fn empty() -> Linter {
+        Linter { rules: vec![] }
+    }
+
+    /// Adds a rule to the linter.
+    pub fn add_rule(&mut self, rule: Box<Rule>) {
+        self.rules.push(rule);
+    }
+
+    /// Removes the rule with the given name.  Returns whether a rule was removed.
+    pub fn disable(&mut self, name: &str) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.name() != name);
+        self.rules.len() != before
+    }
+
+    /// The names of the rules the linter will run.
+    pub fn rule_names(&self) -> Vec<&'static str> {
+        self.rules.iter().map(|r| r.name()).collect()
+    }
+
+    /// Lints the document rooted at `root`, which must have been parsed from `source`.
+    /// Diagnostics are returned ordered by position.
+    pub fn lint<'a>(&self, root: &'a AstNode<'a>, source: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for rule in &self.rules {
+            rule.check(root, source, &mut diagnostics);
+        }
+        diagnostics.sort_by_key(|d| (d.line, d.column));
+        diagnostics
+    }
+}
+
+/// The line a node starts on.  Inline nodes don't record their own position, so it is derived
+/// from their containing block and the line breaks that precede them within it.
+pub fn node_line<'a>(node: &'a AstNode<'a>) -> u32 {
+    let block = match nodes::containing_block(node) {
+        Some(block) => block,
+        None => return 0,
+    };
+    let mut line = block.data.borrow().start_line;
+    for n in block.descendants() {
+        if n.same_node(node) {
+            break;
+        }
+        match n.data.borrow().value {
+            NodeValue::SoftBreak | NodeValue::LineBreak => line += 1,
+            _ => (),
+        }
+    }
+    line
+}
+
+/// The column, starting at 1, at which `text` appears within the text node `node` on line `line`
+/// of the source.  The occurrences of `text` in the source line which belong to the nodes before
+/// `node` on the line, such as in a link's text or destination, are skipped over.
+fn text_column<'a>(
+    node: &'a AstNode<'a>,
+    line: u32,
+    source_line: &str,
+    text: &str,
+) -> Option<usize> {
+    let block = match nodes::containing_block(node) {
+        Some(block) => block,
+        None => return None,
+    };
+    let count = |literal: &[u8]| String::from_utf8_lossy(literal).matches(text).count();
+    let skip = |cursor: usize, occurrences: usize| {
+        source_line[cursor..]
+            .match_indices(text)
+            .take(occurrences)
+            .last()
+            .map_or(cursor, |(i, _)| cursor + i + text.len())
+    };
+
+    let mut current = block.data.borrow().start_line;
+    let mut cursor = 0;
+    for edge in block.traverse() {
+        match edge {
+            NodeEdge::Start(n) => {
+                if n.same_node(node) {
+                    return source_line[cursor..].find(text).map(|i| cursor + i + 1);
+                }
+                match n.data.borrow().value {
+                    NodeValue::SoftBreak | NodeValue::LineBreak => current += 1,
+                    NodeValue::Text(ref literal)
+                    | NodeValue::Code(ref literal)
+                    | NodeValue::HtmlInline(ref literal)
+                        if current == line =>
+                    {
+                        cursor = skip(cursor, count(literal))
+                    }
+                    _ => (),
+                }
+            }
+            NodeEdge::End(n) => match n.data.borrow().value {
+                NodeValue::Link(ref nl) | NodeValue::Image(ref nl) if current == line => {
+                    if destination_follows(n, &nl.url, &source_line[cursor..]) {
+                        cursor = skip(cursor, count(&nl.url) + count(&nl.title));
+                    }
+                }
+                _ => (),
+            },
+        }
+    }
+    None
+}
+
+/// Whether the rest of a source line after the text of `link` begins with its destination in
+/// parentheses, rather than the link being an autolink or a reference link.  The text of an
+/// autolink is its URL, which is where the rest begins; otherwise it may begin earlier within
+/// the text.
+fn destination_follows<'a>(link: &'a AstNode<'a>, url: &[u8], rest: &str) -> bool {
+    let autolink = match link.first_child() {
+        Some(child) if child.next_sibling().is_none() => match child.data.borrow().value {
+            NodeValue::Text(ref literal) => url.ends_with(literal),
+            _ => false,
+        },
+        _ => false,
+    };
+    if autolink {
+        return rest.starts_with("](");
+    }
+    match rest.find("](") {
+        Some(end) => !rest[..end].contains('['),
+        None => false,
+    }
+}
+
+fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut Vec<u8>) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) | NodeValue::Code(ref literal) => {
+            output.extend_from_slice(literal)
+        }
+        NodeValue::Replacement(ref nr) => output.extend_from_slice(&nr.replacement),
+        NodeValue::LineBreak | NodeValue::SoftBreak => output.push(b' '),
+        _ => for n in node.children() {
+            collect_text(n, output);
+        },
+    }
+}
+
+/// The lines of `source`, without line endings, numbered from 1.  Lines end in `\n`, `\r\n` or
+/// `\r`, as for the parser.
+fn source_lines(source: &str) -> Vec<(u32, &str)> {
+    let mut lines = vec![];
+    let mut rest = source;
+    while let Some(end) = rest.find(&['\n', '\r'][..]) {
+        lines.push((lines.len() as u32 + 1, &rest[..end]));
+        let ending = if rest[end..].starts_with("\r\n") { 2 } else { 1 };
+        rest = &rest[end + ending..];
+    }
+    lines.push((lines.len() as u32 + 1, rest));
+    lines
+}
+
+/// The lines which are part of code blocks, in which whitespace and length are significant.
+fn code_block_lines<'a>(root: &'a AstNode<'a>) -> Vec<(u32, u32)> {
+    root.descendants()
+        .filter_map(|n| {
+            let ast = n.data.borrow();
+            match ast.value {
+                NodeValue::CodeBlock(..) | NodeValue::HtmlBlock(..) | NodeValue::Table(..) => {
+                    Some((ast.start_line, ast.end_line))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn in_ranges(line: u32, ranges: &[(u32, u32)]) -> bool {
+    ranges.iter().any(|&(start, end)| line >= start && line <= end)
+}
+
+/// Heading levels should only increase one at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadingIncrement;
+
+impl Rule for HeadingIncrement {
+    fn name(&self) -> &'static str {
+        "heading-increment"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let mut previous = None;
+        for node in root.descendants() {
+            if let NodeValue::Heading(ref nh) = node.data.borrow().value {
+                match previous {
+                    Some(p) if nh.level > p + 1 => diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!(
+                            "heading level should be at most {}, found {}",
+                            p + 1,
+                            nh.level
+                        ),
+                        line: node.data.borrow().start_line,
+                        column: None,
+                    }),
+                    _ => (),
+                }
+                previous = Some(nh.level);
+            }
+        }
+    }
+}
+
+/// Headings should have distinct content.
+#[derive(Debug, Clone, Copy)]
+pub struct DuplicateHeading;
+
+impl Rule for DuplicateHeading {
+    fn name(&self) -> &'static str {
+        "duplicate-heading"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let mut seen = HashMap::new();
+        for node in root.descendants() {
+            if let NodeValue::Heading(..) = node.data.borrow().value {
+                let mut text = vec![];
+                collect_text(node, &mut text);
+                let line = node.data.borrow().start_line;
+                if let Some(&first) = seen.get(&text) {
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!(
+                            "duplicate heading \"{}\", first seen on line {}",
+                            String::from_utf8_lossy(&text),
+                            first
+                        ),
+                        line: line,
+                        column: None,
+                    });
+                } else {
+                    seen.insert(text, line);
+                }
+            }
+        }
+    }
+}
+
+/// URLs should be written as links or autolinks, not bare text.
+#[derive(Debug, Clone, Copy)]
+pub struct BareUrl;
+
+impl Rule for BareUrl {
+    fn name(&self) -> &'static str {
+        "bare-url"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, source: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let lines = source_lines(source);
+        for node in root.descendants() {
+            if let NodeValue::Text(ref text) = node.data.borrow().value {
+                // The text of links, including autolinks, isn't bare.
+                if node.ancestors().any(|n| match n.data.borrow().value {
+                    NodeValue::Link(..) | NodeValue::Image(..) => true,
+                    _ => false,
+                }) {
+                    continue;
+                }
+
+                let found = [&b"http://"[..], b"https://", b"www."]
+                    .iter()
+                    .filter_map(|p| find_bytes(text, p))
+                    .min();
+                if let Some(i) = found {
+                    let end = text[i..]
+                        .iter()
+                        .position(|&c| c == b' ')
+                        .map_or(text.len(), |e| i + e);
+                    let url = String::from_utf8_lossy(&text[i..end]);
+                    let line = node_line(node);
+                    let column = lines
+                        .get((line as usize).wrapping_sub(1))
+                        .and_then(|l| text_column(node, line, l.1, &url));
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!("bare URL {}", url),
+                        line: line,
+                        column: column,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Links should have a destination and text.
+#[derive(Debug, Clone, Copy)]
+pub struct EmptyLink;
+
+impl Rule for EmptyLink {
+    fn name(&self) -> &'static str {
+        "empty-link"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        for node in root.descendants() {
+            if let NodeValue::Link(ref nl) = node.data.borrow().value {
+                let message = if nl.url.is_empty() || nl.url == b"#" {
+                    "link has no destination"
+                } else if node.first_child().is_none() {
+                    "link has no text"
+                } else {
+                    continue;
+                };
+                diagnostics.push(Diagnostic {
+                    rule: self.name(),
+                    message: message.to_string(),
+                    line: node_line(node),
+                    column: None,
+                });
+            }
+        }
+    }
+}
+
+/// Footnote definitions should be referenced.
+#[derive(Debug, Clone, Copy)]
+pub struct UnusedFootnote;
+
+impl Rule for UnusedFootnote {
+    fn name(&self) -> &'static str {
+        "unused-footnote"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        for node in root.children() {
+            if let NodeValue::FootnoteDefinition(ref nfd) = node.data.borrow().value {
+                if nfd.total_references == 0 {
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!(
+                            "footnote [^{}] is never referenced",
+                            String::from_utf8_lossy(&nfd.name)
+                        ),
+                        line: node_line(node),
+                        column: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Footnote references should have a definition.
+#[derive(Debug, Clone, Copy)]
+pub struct MissingFootnote;
+
+impl Rule for MissingFootnote {
+    fn name(&self) -> &'static str {
+        "missing-footnote"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        for node in root.descendants() {
+            if let NodeValue::FootnoteReference(ref nfr) = node.data.borrow().value {
+                if nfr.ix == 0 {
+                    diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!(
+                            "footnote [^{}] is not defined",
+                            String::from_utf8_lossy(&nfr.name)
+                        ),
+                        line: node_line(node),
+                        column: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Bullet lists should all use the same marker as the first one in the document.
+#[derive(Debug, Clone, Copy)]
+pub struct ConsistentListMarker;
+
+impl Rule for ConsistentListMarker {
+    fn name(&self) -> &'static str {
+        "consistent-list-marker"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, _: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let mut expected = None;
+        for node in root.descendants() {
+            if let NodeValue::List(ref nl) = node.data.borrow().value {
+                if nl.list_type != ListType::Bullet {
+                    continue;
+                }
+                match expected {
+                    None => expected = Some(nl.bullet_char),
+                    Some(c) if c != nl.bullet_char => diagnostics.push(Diagnostic {
+                        rule: self.name(),
+                        message: format!(
+                            "list marker should be '{}', found '{}'",
+                            c as char, nl.bullet_char as char
+                        ),
+                        line: node.data.borrow().start_line,
+                        column: None,
+                    }),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Lines should not end in whitespace, other than the two spaces of a hard line break.  Two
+/// spaces which don't make a hard line break, such as at the end of a paragraph, are reported.
+#[derive(Debug, Clone, Copy)]
+pub struct TrailingSpaces;
+
+impl Rule for TrailingSpaces {
+    fn name(&self) -> &'static str {
+        "trailing-spaces"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, source: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let code = code_block_lines(root);
+        let hard_breaks = root
+            .descendants()
+            .filter(|n| match n.data.borrow().value {
+                NodeValue::LineBreak => true,
+                _ => false,
+            })
+            .map(node_line)
+            .collect::<HashSet<_>>();
+        for (number, line) in source_lines(source) {
+            let trimmed = line.trim_end_matches(&[' ', '\t'][..]);
+            let trailing = line.len() - trimmed.len();
+            if trailing == 0
+                || trimmed.is_empty()
+                || (trailing == 2 && !line.ends_with('\t') && hard_breaks.contains(&number))
+                || in_ranges(number, &code)
+            {
+                continue;
+            }
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                message: format!("{} trailing whitespace characters", trailing),
+                line: number,
+                column: Some(trimmed.len() + 1),
+            });
+        }
+    }
+}
+
+/// Lines should be no wider than a given number of columns.  Code blocks, HTML blocks and
+/// tables are exempt.
+#[derive(Debug, Clone, Copy)]
+pub struct LineLength {
+    /// The maximum display width of a line.
+    pub width: usize,
+}
+
+impl Rule for LineLength {
+    fn name(&self) -> &'static str {
+        "line-length"
+    }
+
+    fn check<'a>(&self, root: &'a AstNode<'a>, source: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let exempt = code_block_lines(root);
+        for (number, line) in source_lines(source) {
+            let width = UnicodeWidthStr::width(line);
+            if width <= self.width || in_ranges(number, &exempt) {
+                continue;
+            }
+            diagnostics.push(Diagnostic {
+                rule: self.name(),
+                message: format!("line is {} columns wide, expected at most {}", width, self.width),
+                line: number,
+                column: Some(self.width + 1),
+            });
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Lossless parsing: documents which retain their source, so that top-level blocks which haven't
+//! been modified can be written back out byte-for-byte.
+
+use arena_tree::NodeEdge;
+use cm;
+use nodes::{AstNode, NodeValue};
+use parser::{parse_document, ComrakOptions};
+use std::io::{self, Write};
+use strings;
+use typed_arena::Arena;
+
+/// A document parsed by `parse_document_lossless`.
+///
+/// The AST may be modified freely through `root`.  When formatted, each top-level block that
+/// is still as parsed is copied verbatim from the source, along with the original markers,
+/// indentation, escapes, entity spellings and blank lines around it.  Only blocks that have been
+/// modified or inserted are rendered with the CommonMark formatter.
+///
+/// The granularity is the top-level block: a change anywhere within one, such as to a single
+/// item of a long list or a word in a block quote, renders that whole block with the formatter,
+/// losing its original markers and spelling.  Edits within nested blocks are not tracked
+/// separately.
+#[derive(Debug)]
+pub struct LosslessDocument<'a> {
+    root: &'a AstNode<'a>,
+    source: String,
+    blocks: Vec<SourceBlock<'a>>,
+}
+
+#[derive(Debug)]
+struct SourceBlock<'a> {
+    node: &'a AstNode<'a>,
+    start: usize,
+    end: usize,
+    snapshot: Vec<Option<NodeValue>>,
+}
+
+/// Parse a Markdown document to an AST, retaining the source for lossless formatting.
+///
+/// ```
+/// # use comrak::{parse_document_lossless, Arena, ComrakOptions};
+/// # use comrak::nodes::NodeValue;
+/// let arena = Arena::new();
+/// let input = "Some  *text*\n\n\n+ &amp; a list\n\n***\n";
+/// let doc = parse_document_lossless(&arena, input, &ComrakOptions::default());
+///
+/// let mut output = vec![];
+/// doc.format(&ComrakOptions::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), input);
+///
+/// let text = doc.root().first_child().unwrap().first_child().unwrap();
+/// text.data.borrow_mut().value = NodeValue::Text(b"Other ".to_vec());
+///
+/// let mut output = vec![];
+/// doc.format(&ComrakOptions::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(),
+///            "Other *text*\n\n\n+ &amp; a list\n\n***\n");
+/// ```
+pub fn parse_document_lossless<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &ComrakOptions,
+) -> LosslessDocument<'a> {
+    let root = parse_document(arena, buffer, options);
+
+    let line_starts = strings::line_starts(buffer.as_bytes());
+    let line_offset = |line: u32| -> usize {
+        line_starts
+            .get(line as usize)
+            .cloned()
+            .unwrap_or(buffer.len())
+    };
+
+    let mut blocks = vec![];
+    for node in root.children() {
+        let (start_line, end_line) = {
+            let ast = node.data.borrow();
+            (ast.start_line, ast.end_line)
+        };
+        let start = line_offset(start_line.saturating_sub(1));
+        let mut end = line_offset(end_line);
+
+        // Trailing blank lines belong with the gap after the block, not the block itself.
+        while end > start {
+            let last_line = line_starts
+                .iter()
+                .rev()
+                .cloned()
+                .find(|&s| s < end)
+                .unwrap_or(0);
+            if last_line <= start || !strings::is_blank(&buffer.as_bytes()[last_line..end]) {
+                break;
+            }
+            end = last_line;
+        }
+
+        blocks.push(SourceBlock {
+            node: node,
+            start: start,
+            end: end,
+            snapshot: snapshot(node),
+        });
+    }
+
+    // Blocks may have been reordered during parsing, e.g. footnote definitions.
+    blocks.sort_by_key(|b| b.start);
+
+    LosslessDocument {
+        root: root,
+        source: buffer.to_string(),
+        blocks: blocks,
+    }
+}
+
+impl<'a> LosslessDocument<'a> {
+    /// The root `Document` node of the AST.
+    pub fn root(&self) -> &'a AstNode<'a> {
+        self.root
+    }
+
+    /// The source the document was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Formats the document as CommonMark, reproducing unmodified top-level blocks from the
+    /// source.  If nothing has been modified, the output is identical to the source.
+    pub fn format(&self, options: &ComrakOptions, output: &mut Write) -> io::Result<()> {
+        let source = self.source.as_bytes();
+        let children = self.root.children().collect::<Vec<_>>();
+
+        let unchanged_order = children.len() == self.blocks.len()
+            && children
+                .iter()
+                .all(|c| self.blocks.iter().any(|b| b.node.same_node(c)));
+
+        // When the same blocks remain, emit them in source order; otherwise follow the tree.
+        let order = if unchanged_order {
+            self.blocks.iter().map(|b| b.node).collect()
+        } else {
+            children
+        };
+
+        let mut previous: Option<usize> = None;
+        let mut ended_line = true;
+        for (i, node) in order.iter().enumerate() {
+            let ix = self.blocks.iter().position(|b| b.node.same_node(node));
+
+            match (previous, ix) {
+                (None, Some(0)) if i == 0 => output.write_all(&source[..self.blocks[0].start])?,
+                (Some(p), Some(ix)) if p + 1 == ix => {
+                    output.write_all(&source[self.blocks[p].end..self.blocks[ix].start])?
+                }
+                _ if i > 0 && ended_line => output.write_all(b"\n")?,
+                _ if i > 0 => output.write_all(b"\n\n")?,
+                _ => (),
+            }
+
+            let verbatim = ix.filter(|&ix| {
+                self.blocks[ix].snapshot == snapshot(node)
+                    && !self.needs_end_marker(ix, order.get(i + 1).cloned())
+            });
+
+            match verbatim {
+                Some(ix) => {
+                    let block = &self.blocks[ix];
+                    output.write_all(&source[block.start..block.end])?;
+                    ended_line = source[..block.end]
+                        .last()
+                        .map_or(true, |&c| strings::is_line_end_char(c));
+                }
+                None => {
+                    cm::format_document(node, options, output)?;
+                    ended_line = true;
+                }
+            }
+
+            previous = ix;
+        }
+
+        match previous {
+            Some(p) if p + 1 == self.blocks.len() => {
+                output.write_all(&source[self.blocks[p].end..])?
+            }
+            None if order.is_empty() => output.write_all(source)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Whether the block at `ix` is a list which the CommonMark formatter would end with a
+    /// marker before `next`, but which was not followed by `next` in the source.
+    fn needs_end_marker(&self, ix: usize, next: Option<&'a AstNode<'a>>) -> bool {
+        let next = match next {
+            Some(next) => next,
+            None => return false,
+        };
+        if self.blocks.get(ix + 1).map_or(false, |b| b.node.same_node(next)) {
+            return false;
+        }
+
+        match (&self.blocks[ix].node.data.borrow().value, &next.data.borrow().value) {
+            (&NodeValue::List(..), &NodeValue::List(..))
+            | (&NodeValue::List(..), &NodeValue::CodeBlock(..)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The values of the nodes of the block rooted at `node` in document order, with `None` at the
+/// end of each node's children.  Besides the end marker of a list, this is everything the
+/// CommonMark formatter reads, so a block with an unchanged snapshot renders as it was parsed.
+fn snapshot<'a>(node: &'a AstNode<'a>) -> Vec<Option<NodeValue>> {
+    node.traverse()
+        .map(|edge| match edge {
+            NodeEdge::Start(n) => Some(n.data.borrow().value.clone()),
+            NodeEdge::End(_) => None,
+        })
+        .collect()
+}
@@ -5,7 +5,12 @@ extern crate comrak;
 #[macro_use]
 extern crate clap;
 
-use comrak::{Arena, ComrakOptions};
+use comrak::lint::Linter;
+use comrak::{
+    typographic_replacements, Arena, CodeFenceChar, ComrakOptions, EmphasisDelimiter,
+    FootnoteHtml, HeadingStyle, ListStyleType, OrderedListNumbering, QuoteStyle, SanitizePolicy,
+    SmartPunctuation, UrlPolicy,
+};
 
 use std::boxed::Box;
 use std::collections::BTreeSet;
@@ -34,6 +39,24 @@ fn main() -> Result<(), Box<Error>> {
                 .long("smart")
                 .help("Use smart punctuation"),
         )
+        .arg(
+            clap::Arg::with_name("quote-style")
+                .long("quote-style")
+                .takes_value(true)
+                .possible_values(&["english", "german", "french", "japanese"])
+                .value_name("STYLE")
+                .help("Quotation marks used for smart punctuation"),
+        )
+        .arg(
+            clap::Arg::with_name("no-smart-dashes")
+                .long("no-smart-dashes")
+                .help("Don't convert -- and --- to dashes with smart punctuation"),
+        )
+        .arg(
+            clap::Arg::with_name("no-smart-ellipses")
+                .long("no-smart-ellipses")
+                .help("Don't convert ... to an ellipsis with smart punctuation"),
+        )
         .arg(
             clap::Arg::with_name("github-pre-lang")
                 .long("github-pre-lang")
@@ -51,6 +74,32 @@ fn main() -> Result<(), Box<Error>> {
                 .long("safe")
                 .help("Suppress raw HTML and dangerous URLs"),
         )
+        .arg(
+            clap::Arg::with_name("sanitize")
+                .long("sanitize")
+                .help("Filter raw HTML against an allowlist of tags and attributes"),
+        )
+        .arg(
+            clap::Arg::with_name("base-url")
+                .long("base-url")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Resolve relative link and image URLs against URL"),
+        )
+        .arg(
+            clap::Arg::with_name("external-link-rel")
+                .long("external-link-rel")
+                .takes_value(true)
+                .value_name("REL")
+                .help("Add rel=\"REL\" to links to other hosts"),
+        )
+        .arg(
+            clap::Arg::with_name("image-proxy")
+                .long("image-proxy")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("Load images through TEMPLATE, with {} replaced by the encoded image URL"),
+        )
         .arg(
             clap::Arg::with_name("extension")
                 .short("e")
@@ -65,12 +114,56 @@ fn main() -> Result<(), Box<Error>> {
                     "autolink",
                     "tasklist",
                     "superscript",
+                    "subscript",
+                    "highlight",
+                    "insert",
+                    "underline",
+                    "spoiler",
+                    "container",
+                    "abbreviations",
                     "footnotes",
+                    "inline-footnotes",
                     "description-lists",
+                    "replacements",
                 ])
                 .value_name("EXTENSION")
                 .help("Specify an extension name to use"),
         )
+        .arg(
+            clap::Arg::with_name("autolink-scheme")
+                .long("autolink-scheme")
+                .takes_value(true)
+                .number_of_values(1)
+                .multiple(true)
+                .value_name("SCHEME")
+                .help("Recognise the given scheme, e.g. \"https://\" or \"jira:\", with the autolink extension"),
+        )
+        .arg(
+            clap::Arg::with_name("autolink-relaxed")
+                .long("autolink-relaxed")
+                .help("Relax domain checks in the autolink extension"),
+        )
+        .arg(
+            clap::Arg::with_name("issue-url")
+                .long("issue-url")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("Link issue references like #123 to TEMPLATE, with {} replaced by the number"),
+        )
+        .arg(
+            clap::Arg::with_name("mention-url")
+                .long("mention-url")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("Link mentions like @user to TEMPLATE, with {} replaced by the user name"),
+        )
+        .arg(
+            clap::Arg::with_name("commit-url")
+                .long("commit-url")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("Link commit SHAs to TEMPLATE, with {} replaced by the SHA"),
+        )
         .arg(
             clap::Arg::with_name("format")
                 .short("t")
@@ -89,6 +182,85 @@ fn main() -> Result<(), Box<Error>> {
                 .default_value("0")
                 .help("Specify wrap width (0 = nowrap)"),
         )
+        .arg(
+            clap::Arg::with_name("align-tables")
+                .long("align-tables")
+                .help("Pad table cells to column width when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("list-style")
+                .long("list-style")
+                .takes_value(true)
+                .possible_values(&["dash", "plus", "star"])
+                .value_name("STYLE")
+                .help("Bullet character for bullet lists when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("ordered-list-numbering")
+                .long("ordered-list-numbering")
+                .takes_value(true)
+                .possible_values(&["sequential", "constant"])
+                .value_name("NUMBERING")
+                .help("Numbering of ordered list items when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("emph-delimiter")
+                .long("emph-delimiter")
+                .takes_value(true)
+                .possible_values(&["*", "_"])
+                .value_name("CHAR")
+                .help("Delimiter for emphasis when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("strong-delimiter")
+                .long("strong-delimiter")
+                .takes_value(true)
+                .possible_values(&["*", "_"])
+                .value_name("CHAR")
+                .help("Delimiter for strong emphasis when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("heading-style")
+                .long("heading-style")
+                .takes_value(true)
+                .possible_values(&["atx", "setext"])
+                .value_name("STYLE")
+                .help("Style of level 1 and 2 headings when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("code-fence-char")
+                .long("code-fence-char")
+                .takes_value(true)
+                .possible_values(&["`", "~"])
+                .value_name("CHAR")
+                .help("Fence character for code blocks when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("code-fence-length")
+                .long("code-fence-length")
+                .takes_value(true)
+                .value_name("LENGTH")
+                .default_value("3")
+                .help("Minimum code fence length when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("thematic-break")
+                .long("thematic-break")
+                .takes_value(true)
+                .value_name("BREAK")
+                .help("Thematic break string when outputting CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("check")
+                .long("check")
+                .help("Exit non-zero if any input is not already formatted as CommonMark"),
+        )
+        .arg(
+            clap::Arg::with_name("stats")
+                .long("stats")
+                .conflicts_with("check")
+                .help("Output statistics about the document as JSON instead of formatting it"),
+        )
         .arg(
             clap::Arg::with_name("header-ids")
                 .long("header-ids")
@@ -96,6 +268,41 @@ fn main() -> Result<(), Box<Error>> {
                 .value_name("PREFIX")
                 .help("Use the Comrak header IDs extension, with the given ID prefix"),
         )
+        .arg(
+            clap::Arg::with_name("footnote-id-prefix")
+                .long("footnote-id-prefix")
+                .takes_value(true)
+                .value_name("PREFIX")
+                .help("Prefix footnote and footnote reference IDs with the given prefix"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("lint")
+                .about("Check CommonMark files for style problems")
+                .arg(
+                    clap::Arg::with_name("file")
+                        .value_name("FILE")
+                        .multiple(true)
+                        .help("The CommonMark file to lint; or standard input if none passed"),
+                )
+                .arg(
+                    clap::Arg::with_name("disable")
+                        .long("disable")
+                        .takes_value(true)
+                        .number_of_values(1)
+                        .multiple(true)
+                        .value_name("RULE")
+                        .help("Disable the named rule"),
+                )
+                .arg(
+                    clap::Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["text", "json"])
+                        .default_value("text")
+                        .value_name("FORMAT")
+                        .help("Specify diagnostic output format"),
+                ),
+        )
         .get_matches();
 
     let mut exts = matches
@@ -105,25 +312,101 @@ fn main() -> Result<(), Box<Error>> {
     let options = ComrakOptions {
         hardbreaks: matches.is_present("hardbreaks"),
         smart: matches.is_present("smart"),
+        smart_punctuation: SmartPunctuation {
+            quotes: match matches.value_of("quote-style") {
+                Some("german") => QuoteStyle::German,
+                Some("french") => QuoteStyle::French,
+                Some("japanese") => QuoteStyle::Japanese,
+                _ => QuoteStyle::English,
+            },
+            dashes: !matches.is_present("no-smart-dashes"),
+            ellipses: !matches.is_present("no-smart-ellipses"),
+        },
         github_pre_lang: matches.is_present("github-pre-lang"),
         width: matches
             .value_of("width")
             .unwrap_or("0")
             .parse()
             .unwrap_or(0),
+        align_tables: matches.is_present("align-tables"),
+        list_style: match matches.value_of("list-style") {
+            Some("plus") => ListStyleType::Plus,
+            Some("star") => ListStyleType::Star,
+            _ => ListStyleType::Dash,
+        },
+        ordered_list_numbering: match matches.value_of("ordered-list-numbering") {
+            Some("constant") => OrderedListNumbering::Constant,
+            _ => OrderedListNumbering::Sequential,
+        },
+        emph_delimiter: emphasis_delimiter(matches.value_of("emph-delimiter")),
+        strong_delimiter: emphasis_delimiter(matches.value_of("strong-delimiter")),
+        heading_style: match matches.value_of("heading-style") {
+            Some("setext") => HeadingStyle::Setext,
+            _ => HeadingStyle::Atx,
+        },
+        code_fence_char: match matches.value_of("code-fence-char") {
+            Some("~") => CodeFenceChar::Tilde,
+            _ => CodeFenceChar::Backtick,
+        },
+        code_fence_length: matches
+            .value_of("code-fence-length")
+            .unwrap_or("3")
+            .parse()
+            .unwrap_or(3),
+        thematic_break: matches.value_of("thematic-break").map(|s| s.to_string()),
         default_info_string: matches
             .value_of("default-info-string")
             .map(|e| e.to_owned()),
         safe: matches.is_present("safe"),
+        url_policy: url_policy(&matches),
+        url_rewriter: None,
+        transforms: vec![],
+        sanitize: if matches.is_present("sanitize") {
+            Some(SanitizePolicy::default())
+        } else {
+            None
+        },
         ext_strikethrough: exts.remove("strikethrough"),
         ext_tagfilter: exts.remove("tagfilter"),
         ext_table: exts.remove("table"),
         ext_autolink: exts.remove("autolink"),
+        ext_autolink_schemes: matches
+            .values_of("autolink-scheme")
+            .map_or(vec![], |vals| vals.map(|s| s.to_string()).collect()),
+        ext_autolink_relaxed: matches.is_present("autolink-relaxed"),
+        ext_autolink_filter: None,
+        ext_issue_url: matches.value_of("issue-url").map(|s| s.to_string()),
+        ext_mention_url: matches.value_of("mention-url").map(|s| s.to_string()),
+        ext_commit_url: matches.value_of("commit-url").map(|s| s.to_string()),
+        ext_reference_resolver: None,
         ext_tasklist: exts.remove("tasklist"),
+        ext_tasklist_interactive: false,
         ext_superscript: exts.remove("superscript"),
+        ext_subscript: exts.remove("subscript"),
+        ext_highlight: exts.remove("highlight"),
+        ext_insert: exts.remove("insert"),
+        ext_underline: exts.remove("underline"),
+        ext_spoiler: exts.remove("spoiler"),
+        ext_container: exts.remove("container"),
+        ext_container_html: None,
+        ext_abbreviations: exts.remove("abbreviations"),
         ext_header_ids: matches.value_of("header-ids").map(|s| s.to_string()),
         ext_footnotes: exts.remove("footnotes"),
+        ext_inline_footnotes: exts.remove("inline-footnotes"),
+        ext_footnote_html: FootnoteHtml {
+            id_prefix: matches
+                .value_of("footnote-id-prefix")
+                .unwrap_or("")
+                .to_string(),
+            ..FootnoteHtml::default()
+        },
         ext_description_lists: exts.remove("description-lists"),
+        ext_replacements: if exts.remove("replacements") {
+            typographic_replacements()
+        } else {
+            vec![]
+        },
+        ext_replacements_original: false,
     };
 
     if !exts.is_empty() {
@@ -131,6 +414,83 @@ fn main() -> Result<(), Box<Error>> {
         process::exit(1);
     }
 
+    if let Some(matches) = matches.subcommand_matches("lint") {
+        let mut linter = Linter::new(&options);
+        for rule in matches.values_of("disable").map_or(vec![], |rs| rs.collect()) {
+            if !linter.disable(rule) {
+                eprintln!("unknown rule: {}", rule);
+                process::exit(1);
+            }
+        }
+
+        let mut inputs = vec![];
+        match matches.values_of("file") {
+            None => {
+                let mut s = String::new();
+                std::io::stdin().read_to_string(&mut s)?;
+                inputs.push(("<stdin>".to_string(), s));
+            }
+            Some(fs) => for f in fs {
+                let mut s = String::new();
+                std::fs::File::open(f)?.read_to_string(&mut s)?;
+                inputs.push((f.to_string(), s));
+            },
+        }
+
+        let json = matches.value_of("format") == Some("json");
+        let mut records = vec![];
+        let mut found = false;
+        for (name, input) in inputs {
+            let arena = Arena::new();
+            let root = comrak::parse_document(&arena, &input, &options);
+            for d in linter.lint(root, &input) {
+                found = true;
+                if json {
+                    records.push(format!(
+                        "{{\"file\":{},\"line\":{},\"column\":{},\"rule\":{},\"message\":{}}}",
+                        json_string(&name),
+                        d.line,
+                        d.column.map_or("null".to_string(), |c| c.to_string()),
+                        json_string(d.rule),
+                        json_string(&d.message)
+                    ));
+                } else {
+                    let position = match d.column {
+                        Some(column) => format!("{}:{}", d.line, column),
+                        None => d.line.to_string(),
+                    };
+                    println!("{}:{}: {}: {}", name, position, d.rule, d.message);
+                }
+            }
+        }
+        if json {
+            println!("[{}]", records.join(","));
+        }
+        process::exit(if found { 1 } else { 0 });
+    }
+
+    if matches.is_present("check") {
+        let mut unformatted = false;
+        let inputs: Vec<&str> = matches.values_of("file").map_or(vec![], |fs| fs.collect());
+        if inputs.is_empty() {
+            let mut s = vec![];
+            std::io::stdin().read_to_end(&mut s)?;
+            if !is_formatted(&String::from_utf8(s)?, &options)? {
+                eprintln!("<stdin> is not formatted");
+                unformatted = true;
+            }
+        }
+        for f in inputs {
+            let mut s = String::new();
+            std::fs::File::open(f)?.read_to_string(&mut s)?;
+            if !is_formatted(&s, &options)? {
+                eprintln!("{} is not formatted", f);
+                unformatted = true;
+            }
+        }
+        process::exit(if unformatted { 1 } else { 0 });
+    }
+
     let mut s: Vec<u8> = Vec::with_capacity(2048);
 
     match matches.values_of("file") {
@@ -146,6 +506,41 @@ fn main() -> Result<(), Box<Error>> {
     let arena = Arena::new();
     let root = comrak::parse_document(&arena, &String::from_utf8(s)?, &options);
 
+    if matches.is_present("stats") {
+        let stats = comrak::stats::collect(root);
+        let headings = stats
+            .headings
+            .iter()
+            .map(|h| format!("{{\"level\":{},\"text\":{}}}", h.level, json_string(&h.text)))
+            .collect::<Vec<_>>();
+        let languages = stats
+            .code_languages
+            .iter()
+            .map(|l| json_string(l))
+            .collect::<Vec<_>>();
+        println!(
+            "{{\"words\":{},\"reading_time\":{},\"headings\":[{}],\"first_heading\":{},\
+             \"first_image\":{},\"links\":{},\"images\":{},\"footnotes\":{},\
+             \"code_languages\":[{}]}}",
+            stats.words,
+            stats.reading_time(200),
+            headings.join(","),
+            stats
+                .headings
+                .first()
+                .map_or("null".to_string(), |h| json_string(&h.text)),
+            stats
+                .first_image
+                .as_ref()
+                .map_or("null".to_string(), |i| json_string(i)),
+            stats.links,
+            stats.images,
+            stats.footnotes,
+            languages.join(",")
+        );
+        process::exit(0);
+    }
+
     let formatter = match matches.value_of("format") {
         Some("html") => comrak::format_html,
         Some("commonmark") => comrak::format_commonmark,
@@ -156,3 +551,50 @@ fn main() -> Result<(), Box<Error>> {
 
     process::exit(0);
 }
+
+fn url_policy(matches: &clap::ArgMatches) -> Option<UrlPolicy> {
+    if !matches.is_present("base-url")
+        && !matches.is_present("external-link-rel")
+        && !matches.is_present("image-proxy")
+    {
+        return None;
+    }
+
+    Some(UrlPolicy {
+        base_url: matches.value_of("base-url").map(|s| s.to_string()),
+        external_link_rel: matches.value_of("external-link-rel").map(|s| s.to_string()),
+        image_proxy: matches.value_of("image-proxy").map(|s| s.to_string()),
+        ..UrlPolicy::default()
+    })
+}
+
+fn emphasis_delimiter(value: Option<&str>) -> EmphasisDelimiter {
+    match value {
+        Some("_") => EmphasisDelimiter::Underscore,
+        _ => EmphasisDelimiter::Asterisk,
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn is_formatted(input: &str, options: &ComrakOptions) -> Result<bool, Box<Error>> {
+    let arena = Arena::new();
+    let root = comrak::parse_document(&arena, input, options);
+    let mut output = vec![];
+    comrak::format_commonmark(root, options, &mut output)?;
+    Ok(output == input.as_bytes())
+}
@@ -1,10 +1,14 @@
 //! The CommonMark AST.
 
 use arena_tree::Node;
+use query::node_type_name;
 use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use typed_arena::Arena;
 
 /// The core AST node enum.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum NodeValue {
     /// The root of every CommonMark document.  Contains **blocks**.
     Document,
@@ -79,9 +83,11 @@ pub enum NodeValue {
     /// children.
     ThematicBreak,
 
-    /// **Block**. A footnote definition.  The `Vec<u8>` is the footnote's name.
-    /// Contains other **blocks**.
-    FootnoteDefinition(Vec<u8>),
+    /// **Block**. A footnote definition.  Contains other **blocks**.
+    ///
+    /// Referenced definitions are moved to the end of the document, in the order they are
+    /// first referenced, followed by unreferenced ones, which the HTML formatter omits.
+    FootnoteDefinition(NodeFootnoteDefinition),
 
     /// **Block**. A [table](https://github.github.com/gfm/#tables-extension-) per the GFM spec.
     /// Contains table rows.
@@ -94,6 +100,26 @@ pub enum NodeValue {
     /// **Block**.  A table cell.  Contains **inlines**.
     TableCell,
 
+    /// **Block**.  Content hidden until the reader reveals it.  Enabled with `ext_spoiler`
+    /// option.  Contains other **blocks**.
+    ///
+    /// ``` md
+    /// >! The butler
+    /// >! did it. !<
+    /// ```
+    SpoilerBlock,
+
+    /// **Block**.  A container fenced by lines of at least three colons, with a name and
+    /// attributes, as used for callouts.  Enabled with `ext_container` option.  Contains other
+    /// **blocks**, including nested containers.
+    ///
+    /// ``` md
+    /// ::: warning {#backup .wide}
+    /// Back up your data first.
+    /// :::
+    /// ```
+    Container(NodeContainer),
+
     /// **Inline**.  [Textual content](https://github.github.com/gfm/#textual-content).  All text
     /// in a document will be contained in a `Text` node.
     Text(Vec<u8>),
@@ -126,6 +152,22 @@ pub enum NodeValue {
     /// **Inline**.  Superscript.  Enabled with `ext_superscript` option.
     Superscript,
 
+    /// **Inline**.  Subscript.  Enabled with `ext_subscript` option.
+    Subscript,
+
+    /// **Inline**.  Highlighted text.  Enabled with `ext_highlight` option.
+    Highlight,
+
+    /// **Inline**.  Inserted text.  Enabled with `ext_insert` option.
+    Insert,
+
+    /// **Inline**.  Underlined text.  Enabled with `ext_underline` option.
+    Underline,
+
+    /// **Inline**.  Text hidden until the reader reveals it, written `||text||`.  Enabled with
+    /// `ext_spoiler` option.
+    Spoiler,
+
     /// **Inline**.  A [link](https://github.github.com/gfm/#links) to some URL, with possible
     /// title.
     Link(NodeLink),
@@ -133,12 +175,26 @@ pub enum NodeValue {
     /// **Inline**.  An [image](https://github.github.com/gfm/#images).
     Image(NodeLink),
 
-    /// **Inline**.  A footnote reference; the `Vec<u8>` is the referent footnote's name.
-    FootnoteReference(Vec<u8>),
+    /// **Inline**.  A footnote reference.
+    FootnoteReference(NodeFootnoteReference),
+
+    /// **Inline**.  The checkbox of a
+    /// [task list item](https://github.github.com/gfm/#task-list-items-extension-), enabled with
+    /// the `ext_tasklist` option.  It is the first child of the first paragraph of the list item.
+    TaskItem(NodeTaskItem),
+
+    /// **Inline**.  A typographic replacement, such as `©` for `(c)`, made by the
+    /// `ext_replacements` option.
+    Replacement(NodeReplacement),
+
+    /// **Inline**.  An abbreviation, marked where its text appears as a whole word once it has
+    /// been defined with `*[text]: title` anywhere in the document.  Enabled with
+    /// `ext_abbreviations` option.  Contains the abbreviation's **text**.
+    Abbreviation(NodeAbbreviation),
 }
 
 /// Alignment of a single table cell.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TableAlignment {
     /// Cell content is unaligned.
     None,
@@ -154,7 +210,7 @@ pub enum TableAlignment {
 }
 
 /// The details of a link's destination, or an image's source.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NodeLink {
     /// The URL for the link destination or image source.
     pub url: Vec<u8>,
@@ -166,8 +222,79 @@ pub struct NodeLink {
     pub title: Vec<u8>,
 }
 
+/// The details of a footnote definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeFootnoteDefinition {
+    /// The footnote's label, as written in the document.  Inline footnotes are given a label
+    /// such as `inline-1` that no other footnote uses.
+    pub name: Vec<u8>,
+
+    /// The number of references to the footnote.
+    pub total_references: u32,
+}
+
+/// The details of a footnote reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeFootnoteReference {
+    /// The label of the footnote referred to, as written in the document.
+    pub name: Vec<u8>,
+
+    /// The number of the footnote referred to, counting from 1 in the order footnotes are first
+    /// referenced, or 0 if there is no footnote with this label.
+    pub ix: u32,
+
+    /// Which reference to the footnote this is, counting from 1.
+    pub ref_num: u32,
+}
+
+/// The details of a typographic replacement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeReplacement {
+    /// The text as written in the document, such as `(c)`.
+    pub original: Vec<u8>,
+
+    /// The text it was replaced with, such as `©`.
+    pub replacement: Vec<u8>,
+}
+
+/// The details of a container.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct NodeContainer {
+    /// The number of colons in the fence; at least 3.  The container is closed by a line of at
+    /// least this many colons.
+    pub fence_length: usize,
+
+    /// The container's name, such as `warning`.  May be empty if only attributes are given.
+    pub name: Vec<u8>,
+
+    /// The attributes given in braces after the name, in order.  `#id` is given as `id` and
+    /// `.class` as `class`, once for each class.
+    pub attributes: Vec<(Vec<u8>, Vec<u8>)>,
+
+    /// Any other text on the opening line, such as the title of a callout.
+    pub title: Vec<u8>,
+}
+
+/// The details of an abbreviation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeAbbreviation {
+    /// The abbreviation's definition, such as `Hyper Text Markup Language` for `HTML`.
+    pub title: Vec<u8>,
+}
+
+/// The state of a task list item's checkbox.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTaskItem {
+    /// Whether the checkbox is checked.
+    pub checked: bool,
+
+    /// The character between the brackets; `b' '` if unchecked, and `b'x'` or `b'X'` if
+    /// checked.
+    pub symbol: u8,
+}
+
 /// The metadata of a list; the kind of list, the delimiter used and so on.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct NodeList {
     /// The kind of list (bullet (unordered) or ordered).
     pub list_type: ListType,
@@ -193,7 +320,7 @@ pub struct NodeList {
 }
 
 /// The metadata of a description list
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct NodeDescriptionItem {
     #[doc(hidden)]
     pub marker_offset: usize,
@@ -235,7 +362,7 @@ impl Default for ListDelimType {
 }
 
 /// The metadata and data of a code block (fenced or indented).
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub struct NodeCodeBlock {
     /// Whether the code block is fenced.
     pub fenced: bool,
@@ -260,7 +387,7 @@ pub struct NodeCodeBlock {
 }
 
 /// The metadata of a heading.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
 pub struct NodeHeading {
     /// The level of the header; from 1 to 6 for ATX headings, 1 or 2 for setext headings.
     pub level: u32,
@@ -270,7 +397,7 @@ pub struct NodeHeading {
 }
 
 /// The metadata of an included HTML block.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NodeHtmlBlock {
     #[doc(hidden)]
     pub block_type: u8,
@@ -300,7 +427,9 @@ impl NodeValue {
             | NodeValue::ThematicBreak
             | NodeValue::Table(..)
             | NodeValue::TableRow(..)
-            | NodeValue::TableCell => true,
+            | NodeValue::TableCell
+            | NodeValue::SpoilerBlock
+            | NodeValue::Container(..) => true,
             _ => false,
         }
     }
@@ -345,7 +474,7 @@ impl NodeValue {
 /// A single node in the CommonMark AST.
 ///
 /// The struct contains metadata about the node's position in the original document, and the core
-/// enum, `NodeValue`.
+/// enum, `NodeValue`.  New nodes are created with `Ast::new`.
 #[derive(Debug, Clone)]
 pub struct Ast {
     /// The node value itself.
@@ -354,6 +483,9 @@ pub struct Ast {
     /// The line in the input document the node starts at.
     pub start_line: u32,
 
+    pub(crate) end_line: u32,
+    pub(crate) start_column: usize,
+
     #[doc(hidden)]
     pub content: Vec<u8>,
     #[doc(hidden)]
@@ -362,12 +494,41 @@ pub struct Ast {
     pub last_line_blank: bool,
 }
 
+impl Ast {
+    /// Creates a closed node with the given value, for building or modifying a document.  It
+    /// has no position in any source document.
+    pub fn new(value: NodeValue) -> Ast {
+        Ast {
+            value: value,
+            content: vec![],
+            start_line: 0,
+            end_line: 0,
+            start_column: 0,
+            open: false,
+            last_line_blank: false,
+        }
+    }
+
+    /// The line in the input document the node ends at.  Only set for block nodes.
+    pub fn end_line(&self) -> u32 {
+        self.end_line
+    }
+
+    /// The column in bytes, counting from 1, at which the node starts on its first line.  Only
+    /// set for block nodes.
+    pub fn start_column(&self) -> usize {
+        self.start_column
+    }
+}
+
 #[doc(hidden)]
-pub fn make_block(value: NodeValue, start_line: u32) -> Ast {
+pub fn make_block(value: NodeValue, start_line: u32, start_column: usize) -> Ast {
     Ast {
         value: value,
         content: vec![],
         start_line: start_line,
+        end_line: start_line,
+        start_column: start_column,
         open: true,
         last_line_blank: false,
     }
@@ -385,7 +546,7 @@ pub fn last_child_is_open<'a>(node: &'a AstNode<'a>) -> bool {
     node.last_child().map_or(false, |n| n.data.borrow().open)
 }
 
-#[doc(hidden)]
+/// Returns whether `node` may contain a child node with the value `child`.
 pub fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) -> bool {
     if let NodeValue::Document = *child {
         return false;
@@ -394,6 +555,8 @@ pub fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) -> bool {
     match node.data.borrow().value {
         NodeValue::Document
         | NodeValue::BlockQuote
+        | NodeValue::SpoilerBlock
+        | NodeValue::Container(..)
         | NodeValue::FootnoteDefinition(_)
         | NodeValue::DescriptionTerm
         | NodeValue::DescriptionDetails
@@ -423,9 +586,21 @@ pub fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) -> bool {
         | NodeValue::Heading(..)
         | NodeValue::Emph
         | NodeValue::Strong
+        | NodeValue::Strikethrough
+        | NodeValue::Superscript
+        | NodeValue::Subscript
+        | NodeValue::Highlight
+        | NodeValue::Insert
+        | NodeValue::Underline
+        | NodeValue::Spoiler
         | NodeValue::Link(..)
         | NodeValue::Image(..) => !child.block(),
 
+        NodeValue::Abbreviation(..) => match *child {
+            NodeValue::Text(..) => true,
+            _ => false,
+        },
+
         NodeValue::Table(..) => match *child {
             NodeValue::TableRow(..) => true,
             _ => false,
@@ -444,7 +619,14 @@ pub fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) -> bool {
             | NodeValue::Link(..)
             | NodeValue::Image(..)
             | NodeValue::Strikethrough
-            | NodeValue::HtmlInline(..) => true,
+            | NodeValue::Subscript
+            | NodeValue::Highlight
+            | NodeValue::Insert
+            | NodeValue::Underline
+            | NodeValue::Spoiler
+            | NodeValue::HtmlInline(..)
+            | NodeValue::Replacement(..)
+            | NodeValue::Abbreviation(..) => true,
             _ => false,
         },
 
@@ -478,3 +660,187 @@ pub fn containing_block<'a>(node: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
     }
     None
 }
+
+/// Allocates a new node with the given value in `arena`.
+///
+/// ```
+/// # use comrak::{format_commonmark, Arena, ComrakOptions};
+/// # use comrak::nodes::{new_node, NodeHeading, NodeValue};
+/// let arena = Arena::new();
+/// let heading = new_node(&arena, NodeValue::Heading(NodeHeading { level: 2, setext: false }));
+/// heading.append(new_node(&arena, NodeValue::Text(b"Title".to_vec())));
+/// let root = new_node(&arena, NodeValue::Document);
+/// root.append(heading);
+///
+/// let mut output = vec![];
+/// format_commonmark(root, &ComrakOptions::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "## Title\n");
+/// ```
+pub fn new_node<'a>(arena: &'a Arena<AstNode<'a>>, value: NodeValue) -> &'a AstNode<'a> {
+    arena.alloc(Node::new(RefCell::new(Ast::new(value))))
+}
+
+/// Builds a subtree of new nodes, checking that each child is allowed in its parent.
+///
+/// ```
+/// # use comrak::{format_html, Arena, ComrakOptions};
+/// # use comrak::nodes::{NodeBuilder, NodeValue};
+/// let arena = Arena::new();
+/// let root = NodeBuilder::new(&arena, NodeValue::Document)
+///     .child(
+///         NodeBuilder::new(&arena, NodeValue::Paragraph)
+///             .text("Hello, ")
+///             .child(NodeBuilder::new(&arena, NodeValue::Emph).text("world").build().unwrap())
+///             .build()
+///             .unwrap(),
+///     )
+///     .build()
+///     .unwrap();
+///
+/// let mut output = vec![];
+/// format_html(root, &ComrakOptions::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "<p>Hello, <em>world</em></p>\n");
+///
+/// assert!(NodeBuilder::new(&arena, NodeValue::Paragraph)
+///     .child(NodeBuilder::new(&arena, NodeValue::Paragraph).build().unwrap())
+///     .build()
+///     .is_err());
+/// ```
+pub struct NodeBuilder<'a> {
+    arena: &'a Arena<AstNode<'a>>,
+    node: &'a AstNode<'a>,
+}
+
+impl<'a> fmt::Debug for NodeBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NodeBuilder")
+            .field("node", &self.node)
+            .finish()
+    }
+}
+
+impl<'a> NodeBuilder<'a> {
+    /// Starts building a node with the given value.
+    pub fn new(arena: &'a Arena<AstNode<'a>>, value: NodeValue) -> NodeBuilder<'a> {
+        NodeBuilder {
+            arena: arena,
+            node: new_node(arena, value),
+        }
+    }
+
+    /// Appends a child node.
+    pub fn child(self, child: &'a AstNode<'a>) -> NodeBuilder<'a> {
+        self.node.append(child);
+        self
+    }
+
+    /// Appends a child node with the given value.
+    pub fn value(self, value: NodeValue) -> NodeBuilder<'a> {
+        let child = new_node(self.arena, value);
+        self.child(child)
+    }
+
+    /// Appends a `Text` child node.
+    pub fn text(self, text: &str) -> NodeBuilder<'a> {
+        self.value(NodeValue::Text(text.as_bytes().to_vec()))
+    }
+
+    /// Returns the built node, after checking that every node in it is allowed in its parent.
+    pub fn build(self) -> Result<&'a AstNode<'a>, InvalidChild> {
+        validate(self.node)?;
+        Ok(self.node)
+    }
+}
+
+/// An error indicating that a node is not allowed in its parent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidChild {
+    /// The parent's value.
+    pub parent: NodeValue,
+
+    /// The value of the child which isn't allowed in the parent.
+    pub child: NodeValue,
+}
+
+impl fmt::Display for InvalidChild {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} cannot contain {}",
+            node_type_name(&self.parent),
+            node_type_name(&self.child)
+        )
+    }
+}
+
+impl Error for InvalidChild {
+    fn description(&self) -> &str {
+        "node cannot contain child"
+    }
+}
+
+/// Checks that every node in the tree rooted at `root` is allowed in its parent, per
+/// `can_contain_type`.
+pub fn validate<'a>(root: &'a AstNode<'a>) -> Result<(), InvalidChild> {
+    for node in root.descendants() {
+        for child in node.children() {
+            let child = &child.data.borrow().value;
+            if !can_contain_type(node, child) {
+                return Err(InvalidChild {
+                    parent: node.data.borrow().value.clone(),
+                    child: child.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Appends `child` to `parent` if `parent` can contain it.
+pub fn append_checked<'a>(
+    parent: &'a AstNode<'a>,
+    child: &'a AstNode<'a>,
+) -> Result<(), InvalidChild> {
+    let value = child.data.borrow().value.clone();
+    if !can_contain_type(parent, &value) {
+        return Err(InvalidChild {
+            parent: parent.data.borrow().value.clone(),
+            child: value,
+        });
+    }
+    parent.append(child);
+    Ok(())
+}
+
+/// Replaces `node` and its subtree with `replacement`, which is detached from wherever it was.
+pub fn replace<'a>(node: &'a AstNode<'a>, replacement: &'a AstNode<'a>) {
+    node.insert_after(replacement);
+    node.detach();
+}
+
+/// Removes `node` from the tree, moving its children into its place.
+///
+/// ```
+/// # use comrak::{format_commonmark, parse_document, Arena, ComrakOptions};
+/// # use comrak::nodes::unwrap;
+/// let arena = Arena::new();
+/// let root = parse_document(&arena, "Some *emphasised* text.\n", &ComrakOptions::default());
+/// let emph = root.first_child().unwrap().children().nth(1).unwrap();
+/// unwrap(emph);
+///
+/// let mut output = vec![];
+/// format_commonmark(root, &ComrakOptions::default(), &mut output).unwrap();
+/// assert_eq!(String::from_utf8(output).unwrap(), "Some emphasised text.\n");
+/// ```
+pub fn unwrap<'a>(node: &'a AstNode<'a>) {
+    while let Some(child) = node.first_child() {
+        node.insert_before(child);
+    }
+    node.detach();
+}
+
+/// Puts `wrapper` in place of `node`, and moves `node` into it as its last child.
+pub fn wrap<'a>(node: &'a AstNode<'a>, wrapper: &'a AstNode<'a>) {
+    node.insert_before(wrapper);
+    wrapper.append(node);
+}
@@ -0,0 +1,58 @@
+use ctype::isalnum;
+use nodes::{AstNode, NodeAbbreviation, NodeValue};
+use parser::inlines::make_inline;
+use std::collections::HashMap;
+use typed_arena::Arena;
+
+pub fn process_abbreviations<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    node: &'a AstNode<'a>,
+    contents: &mut Vec<u8>,
+    abbreviations: &HashMap<Vec<u8>, Vec<u8>>,
+) {
+    let len = contents.len();
+    let mut i = 0;
+
+    while i < len {
+        if i == 0 || !is_word_byte(contents[i - 1]) {
+            if let Some((text, title)) = abbreviation_match(&contents[i..], abbreviations) {
+                let inl = make_inline(
+                    arena,
+                    NodeValue::Abbreviation(NodeAbbreviation {
+                        title: title.clone(),
+                    }),
+                );
+                inl.append(make_inline(arena, NodeValue::Text(text.clone())));
+                node.insert_after(inl);
+
+                let skip = text.len();
+                if i + skip < len {
+                    let remain = contents[i + skip..].to_vec();
+                    inl.insert_after(make_inline(arena, NodeValue::Text(remain)));
+                }
+                contents.truncate(i);
+                return;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Bytes which may be part of a word, including those of any non-ASCII character, so that
+/// abbreviations only match whole words.
+fn is_word_byte(c: u8) -> bool {
+    isalnum(c) || c == b'_' || c >= 0x80
+}
+
+/// The longest abbreviation `data` starts with as a whole word, if any.
+fn abbreviation_match<'h>(
+    data: &[u8],
+    abbreviations: &'h HashMap<Vec<u8>, Vec<u8>>,
+) -> Option<(&'h Vec<u8>, &'h Vec<u8>)> {
+    abbreviations
+        .iter()
+        .filter(|&(text, _)| {
+            data.starts_with(text) && !data.get(text.len()).map_or(false, |&c| is_word_byte(c))
+        })
+        .max_by_key(|&(text, _)| text.len())
+}
@@ -1,6 +1,7 @@
 use ctype::{isalnum, isalpha, isspace};
 use nodes::{AstNode, NodeLink, NodeValue};
 use parser::inlines::make_inline;
+use parser::ComrakOptions;
 use std::str;
 use typed_arena::Arena;
 use unicode_categories::UnicodeCategories;
@@ -9,6 +10,7 @@ pub fn process_autolinks<'a>(
     arena: &'a Arena<AstNode<'a>>,
     node: &'a AstNode<'a>,
     contents: &mut Vec<u8>,
+    options: &ComrakOptions,
 ) {
     let len = contents.len();
     let mut i = 0;
@@ -19,19 +21,19 @@ pub fn process_autolinks<'a>(
         while i < len {
             match contents[i] {
                 b':' => {
-                    post_org = url_match(arena, contents, i);
+                    post_org = url_match(arena, contents, i, options);
                     if post_org.is_some() {
                         break;
                     }
                 }
                 b'w' => {
-                    post_org = www_match(arena, contents, i);
+                    post_org = www_match(arena, contents, i, options);
                     if post_org.is_some() {
                         break;
                     }
                 }
                 b'@' => {
-                    post_org = email_match(arena, contents, i);
+                    post_org = email_match(arena, contents, i, options);
                     if post_org.is_some() {
                         break;
                     }
@@ -59,6 +61,7 @@ fn www_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    options: &ComrakOptions,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
         static ref WWW_DELIMS: [bool; 256] = {
@@ -78,7 +81,7 @@ fn www_match<'a>(
         return None;
     }
 
-    let mut link_end = match check_domain(&contents[i..], false) {
+    let mut link_end = match check_domain(&contents[i..], false, options.ext_autolink_relaxed) {
         None => return None,
         Some(link_end) => link_end,
     };
@@ -92,22 +95,10 @@ fn www_match<'a>(
     let mut url = b"http://".to_vec();
     url.extend_from_slice(&contents[i..link_end + i]);
 
-    let inl = make_inline(
-        arena,
-        NodeValue::Link(NodeLink {
-            url: url,
-            title: vec![],
-        }),
-    );
-
-    inl.append(make_inline(
-        arena,
-        NodeValue::Text(contents[i..link_end + i].to_vec()),
-    ));
-    Some((inl, 0, link_end))
+    make_autolink(arena, url, &contents[i..link_end + i], options).map(|inl| (inl, 0, link_end))
 }
 
-fn check_domain(data: &[u8], allow_short: bool) -> Option<usize> {
+fn check_domain(data: &[u8], allow_short: bool, relaxed: bool) -> Option<usize> {
     let mut np = 0;
     let mut uscore1 = 0;
     let mut uscore2 = 0;
@@ -120,14 +111,16 @@ fn check_domain(data: &[u8], allow_short: bool) -> Option<usize> {
             uscore2 = 0;
             np += 1;
         } else if !is_valid_hostchar(c) && c != '-' {
-            if uscore1 == 0 && uscore2 == 0 && np > 0 {
+            if relaxed && i > 0 || uscore1 == 0 && uscore2 == 0 && np > 0 {
                 return Some(i);
             }
             return None;
         }
     }
 
-    if uscore1 > 0 || uscore2 > 0 {
+    if relaxed {
+        Some(data.len())
+    } else if uscore1 > 0 || uscore2 > 0 {
         None
     } else if allow_short || np > 0 {
         Some(data.len())
@@ -205,30 +198,55 @@ fn url_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    options: &ComrakOptions,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
-        static ref SCHEMES: Vec<&'static [u8]> = vec![b"http", b"https", b"ftp"];
+        static ref DEFAULT_SCHEMES: Vec<String> = vec![
+            "http://".to_string(),
+            "https://".to_string(),
+            "ftp://".to_string(),
+        ];
     }
 
+    let schemes = if options.ext_autolink_schemes.is_empty() {
+        &*DEFAULT_SCHEMES
+    } else {
+        &options.ext_autolink_schemes
+    };
+
     let size = contents.len();
 
-    if size - i < 4 || contents[i + 1] != b'/' || contents[i + 2] != b'/' {
-        return None;
-    }
+    // Scheme names may contain digits, `+`, `-` and `.` after the first letter, so each
+    // scheme's name is matched back from the colon, and must not follow a letter.
+    let (scheme, rewind) = match schemes
+        .iter()
+        .map(|s| s.as_bytes())
+        .filter_map(|s| {
+            let rewind = s.iter().position(|&c| c == b':')?;
+            if rewind > i
+                || !contents[i - rewind..].starts_with(s)
+                || (rewind < i && isalpha(contents[i - rewind - 1]))
+            {
+                return None;
+            }
+            Some((s, rewind))
+        })
+        .max_by_key(|&(_, rewind)| rewind)
+    {
+        None => return None,
+        Some(m) => m,
+    };
 
-    let mut rewind = 0;
-    while rewind < i && isalpha(contents[i - rewind - 1]) {
-        rewind += 1;
-    }
+    // The offset from `i` at which the part after the scheme and separator begins.
+    let rest = scheme.len() - rewind;
 
-    let cond = |s: &&[u8]| size - i + rewind >= s.len() && &&contents[i - rewind..i] == s;
-    if !SCHEMES.iter().any(cond) {
-        return None;
-    }
-
-    let mut link_end = match check_domain(&contents[i + 3..], true) {
-        None => return None,
-        Some(link_end) => link_end,
+    let mut link_end = if scheme.ends_with(b"//") {
+        match check_domain(&contents[i + rest..], true, options.ext_autolink_relaxed) {
+            None => return None,
+            Some(domain_end) => rest + domain_end,
+        }
+    } else {
+        rest
     };
 
     while link_end < size - i && !isspace(contents[i + link_end]) {
@@ -236,24 +254,45 @@ fn url_match<'a>(
     }
 
     link_end = autolink_delim(&contents[i..], link_end);
+    if link_end <= rest {
+        return None;
+    }
 
     let url = contents[i - rewind..i + link_end].to_vec();
+    make_autolink(arena, url.clone(), &url, options).map(|inl| (inl, rewind, rewind + link_end))
+}
+
+fn make_autolink<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    url: Vec<u8>,
+    text: &[u8],
+    options: &ComrakOptions,
+) -> Option<&'a AstNode<'a>> {
+    let url = match options.ext_autolink_filter {
+        None => url,
+        Some(ref filter) => match filter(&String::from_utf8_lossy(&url)) {
+            None => return None,
+            Some(url) => url.into_bytes(),
+        },
+    };
+
     let inl = make_inline(
         arena,
         NodeValue::Link(NodeLink {
-            url: url.clone(),
+            url: url,
             title: vec![],
         }),
     );
 
-    inl.append(make_inline(arena, NodeValue::Text(url)));
-    Some((inl, rewind, rewind + link_end))
+    inl.append(make_inline(arena, NodeValue::Text(text.to_vec())));
+    Some(inl)
 }
 
 fn email_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    options: &ComrakOptions,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
         static ref EMAIL_OK_SET: [bool; 256] = {
@@ -322,17 +361,6 @@ fn email_match<'a>(
     let mut url = b"mailto:".to_vec();
     url.extend_from_slice(&contents[i - rewind..link_end + i]);
 
-    let inl = make_inline(
-        arena,
-        NodeValue::Link(NodeLink {
-            url: url,
-            title: vec![],
-        }),
-    );
-
-    inl.append(make_inline(
-        arena,
-        NodeValue::Text(contents[i - rewind..link_end + i].to_vec()),
-    ));
-    Some((inl, rewind, rewind + link_end))
+    make_autolink(arena, url, &contents[i - rewind..link_end + i], options)
+        .map(|inl| (inl, rewind, rewind + link_end))
 }
@@ -1,8 +1,10 @@
 use arena_tree::Node;
-use ctype::{ispunct, isspace};
+use ctype::{isalnum, ispunct, isspace};
 use entity;
-use nodes::{Ast, AstNode, NodeLink, NodeValue};
-use parser::{unwrap_into_2, unwrap_into_copy, AutolinkType, ComrakOptions, Reference};
+use nodes::{Ast, AstNode, NodeFootnoteReference, NodeLink, NodeReplacement, NodeValue};
+use parser::{
+    is_replaceable, unwrap_into_2, unwrap_into_copy, AutolinkType, ComrakOptions, Reference,
+};
 use scanners;
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
@@ -29,6 +31,7 @@ pub struct Subject<'a: 'd, 'r, 'o, 'd, 'i> {
     special_chars: [bool; 256],
     skip_chars: [bool; 256],
     smart_chars: [bool; 256],
+    replacement_chars: [bool; 256],
 }
 
 pub struct Delimiter<'a: 'd, 'd> {
@@ -46,6 +49,7 @@ struct Bracket<'a: 'd, 'd> {
     inl_text: &'a AstNode<'a>,
     position: usize,
     image: bool,
+    inline_footnote: bool,
     active: bool,
     bracket_after: bool,
 }
@@ -72,6 +76,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             special_chars: [false; 256],
             skip_chars: [false; 256],
             smart_chars: [false; 256],
+            replacement_chars: [false; 256],
         };
         for &c in &[
             b'\n', b'\r', b'_', b'*', b'"', b'`', b'\\', b'&', b'<', b'[', b']', b'!',
@@ -82,12 +87,29 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             s.special_chars[b'~' as usize] = true;
             s.skip_chars[b'~' as usize] = true;
         }
-        if options.ext_superscript {
+        if options.ext_subscript {
+            s.special_chars[b'~' as usize] = true;
+        }
+        if options.ext_highlight {
+            s.special_chars[b'=' as usize] = true;
+        }
+        if options.ext_insert {
+            s.special_chars[b'+' as usize] = true;
+        }
+        if options.ext_spoiler {
+            s.special_chars[b'|' as usize] = true;
+        }
+        if options.ext_superscript || options.ext_footnotes && options.ext_inline_footnotes {
             s.special_chars[b'^' as usize] = true;
         }
         for &c in &[b'"', b'\'', b'.', b'-'] {
             s.smart_chars[c as usize] = true;
         }
+        for replacement in &options.ext_replacements {
+            if is_replaceable(replacement.0.as_bytes()) {
+                s.replacement_chars[replacement.0.as_bytes()[0] as usize] = true;
+            }
+        }
         s
     }
 
@@ -110,6 +132,9 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             '&' => new_inl = Some(self.handle_entity()),
             '<' => new_inl = Some(self.handle_pointy_brace()),
             '*' | '_' | '\'' | '"' => new_inl = Some(self.handle_delim(c as u8)),
+            '-' | '.' if self.find_replacement().is_some() => {
+                new_inl = self.handle_replacement()
+            }
             '-' => new_inl = Some(self.handle_hyphen()),
             '.' => new_inl = Some(self.handle_period()),
             '[' => {
@@ -130,13 +155,44 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                     new_inl = Some(make_inline(self.arena, NodeValue::Text(b"!".to_vec())));
                 }
             }
-            _ => if self.options.ext_strikethrough && c == '~' {
+            _ if self.find_replacement().is_some() => {
+                new_inl = self.handle_replacement()
+            }
+            _ => if (self.options.ext_strikethrough || self.options.ext_subscript) && c == '~'
+                && !(self.options.ext_subscript && self.in_autolink_candidate())
+            {
                 new_inl = Some(self.handle_delim(b'~'));
+            } else if self.options.ext_highlight && c == '=' && !self.in_autolink_candidate() {
+                new_inl = Some(self.handle_delim(b'='));
+            } else if self.options.ext_insert && c == '+' && !self.in_autolink_candidate() {
+                new_inl = Some(self.handle_delim(b'+'));
+            } else if self.options.ext_spoiler && c == '|' {
+                new_inl = Some(self.handle_delim(b'|'));
+            } else if c == '^'
+                && self.options.ext_footnotes
+                && self.options.ext_inline_footnotes
+                && self.peek_char_n(1) == Some(&(b'['))
+            {
+                self.pos += 2;
+                let inl = make_inline(self.arena, NodeValue::Text(b"^[".to_vec()));
+                new_inl = Some(inl);
+                self.push_bracket(false, inl);
+                let len = self.brackets.len();
+                self.brackets[len - 1].inline_footnote = true;
             } else if self.options.ext_superscript && c == '^' {
                 new_inl = Some(self.handle_delim(b'^'));
             } else {
+                // `^` may be special only to find inline footnotes, delimiters aren't special in
+                // URLs, and other characters are special only to find replacements, so they can
+                // start a text run.
+                let startpos = self.pos;
+                if c == '^' || c == '~' || c == '=' || c == '+'
+                    || self.replacement_chars[c as usize]
+                {
+                    self.pos += 1;
+                }
                 let endpos = self.find_special_char();
-                let mut contents = self.input[self.pos..endpos].to_vec();
+                let mut contents = self.input[startpos..endpos].to_vec();
                 self.pos = endpos;
 
                 if self
@@ -226,12 +282,21 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             i['_' as usize] = stack_bottom;
             i['\'' as usize] = stack_bottom;
             i['"' as usize] = stack_bottom;
-            if self.options.ext_strikethrough {
+            if self.options.ext_strikethrough || self.options.ext_subscript {
                 i['~' as usize] = stack_bottom;
             }
             if self.options.ext_superscript {
                 i['^' as usize] = stack_bottom;
             }
+            if self.options.ext_highlight {
+                i['=' as usize] = stack_bottom;
+            }
+            if self.options.ext_insert {
+                i['+' as usize] = stack_bottom;
+            }
+            if self.options.ext_spoiler {
+                i['|' as usize] = stack_bottom;
+            }
         }
 
         // This is traversing the stack from the top to the bottom, setting `closer` to
@@ -270,6 +335,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                     ) {
                     if opener.unwrap().can_open
                         && opener.unwrap().delim_char == closer.unwrap().delim_char
+                        && self.delimiter_lengths_match(opener.unwrap(), closer.unwrap())
                     {
                         // This is a bit convoluted; see points 9 and 10 here:
                         // http://spec.commonmark.org/0.28/#can-open-emphasis.
@@ -299,8 +365,12 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                 // both get passed.
                 if closer.unwrap().delim_char == b'*'
                     || closer.unwrap().delim_char == b'_'
-                    || (self.options.ext_strikethrough && closer.unwrap().delim_char == b'~')
+                    || ((self.options.ext_strikethrough || self.options.ext_subscript)
+                        && closer.unwrap().delim_char == b'~')
                     || (self.options.ext_superscript && closer.unwrap().delim_char == b'^')
+                    || (self.options.ext_highlight && closer.unwrap().delim_char == b'=')
+                    || (self.options.ext_insert && closer.unwrap().delim_char == b'+')
+                    || (self.options.ext_spoiler && closer.unwrap().delim_char == b'|')
                 {
                     if opener_found {
                         // Finally, here's the happy case where the delimiters
@@ -324,6 +394,9 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                         closer = closer.unwrap().next.get();
                     }
                 } else if closer.unwrap().delim_char == b'\'' {
+                    let quotes = self.options.smart_punctuation.quotes;
+                    // A closing single quote without an opener is an apostrophe.
+                    let close = if opener_found { quotes.single().1 } else { "’" };
                     *closer
                         .unwrap()
                         .inl
@@ -331,7 +404,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                         .borrow_mut()
                         .value
                         .text_mut()
-                        .unwrap() = "’".to_string().into_bytes();
+                        .unwrap() = close.to_string().into_bytes();
                     if opener_found {
                         *opener
                             .unwrap()
@@ -340,10 +413,11 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                             .borrow_mut()
                             .value
                             .text_mut()
-                            .unwrap() = "‘".to_string().into_bytes();
+                            .unwrap() = quotes.single().0.to_string().into_bytes();
                     }
                     closer = closer.unwrap().next.get();
                 } else if closer.unwrap().delim_char == b'"' {
+                    let quotes = self.options.smart_punctuation.quotes;
                     *closer
                         .unwrap()
                         .inl
@@ -351,7 +425,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                         .borrow_mut()
                         .value
                         .text_mut()
-                        .unwrap() = "”".to_string().into_bytes();
+                        .unwrap() = quotes.double().1.to_string().into_bytes();
                     if opener_found {
                         *opener
                             .unwrap()
@@ -360,7 +434,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
                             .borrow_mut()
                             .value
                             .text_mut()
-                            .unwrap() = "“".to_string().into_bytes();
+                            .unwrap() = quotes.double().0.to_string().into_bytes();
                     }
                     closer = closer.unwrap().next.get();
                 }
@@ -440,6 +514,9 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             if self.options.smart && self.smart_chars[self.input[n] as usize] {
                 return n;
             }
+            if self.replacement_chars[self.input[n] as usize] {
+                return n;
+            }
         }
 
         self.input.len()
@@ -529,10 +606,11 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         let contents = if c == b'\'' && self.options.smart {
             b"\xE2\x80\x99".to_vec()
         } else if c == b'"' && self.options.smart {
+            let (open, close) = self.options.smart_punctuation.quotes.double();
             if can_close {
-                b"\xE2\x90\x9D".to_vec()
+                close.as_bytes().to_vec()
             } else {
-                b"\xE2\x80\x9C".to_vec()
+                open.as_bytes().to_vec()
             }
         } else {
             self.input[self.pos - numdelims..self.pos].to_vec()
@@ -550,11 +628,12 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         let start = self.pos;
         self.pos += 1;
 
-        if !self.options.smart || self.peek_char().map_or(false, |&c| c != b'-') {
+        let smart = self.options.smart && self.options.smart_punctuation.dashes;
+        if !smart || self.peek_char().map_or(false, |&c| c != b'-') {
             return make_inline(self.arena, NodeValue::Text(vec![b'-']));
         }
 
-        while self.options.smart && self.peek_char().map_or(false, |&c| c == b'-') {
+        while self.peek_char().map_or(false, |&c| c == b'-') {
             self.pos += 1;
         }
 
@@ -581,9 +660,89 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         make_inline(self.arena, NodeValue::Text(buf))
     }
 
+    pub fn handle_replacement(&mut self) -> Option<&'a AstNode<'a>> {
+        let options = self.options;
+        let replacement = match self.find_replacement() {
+            Some(i) => &options.ext_replacements[i],
+            None => return None,
+        };
+
+        self.pos += replacement.0.len();
+        Some(make_inline(
+            self.arena,
+            NodeValue::Replacement(NodeReplacement {
+                original: replacement.0.as_bytes().to_vec(),
+                replacement: replacement.1.as_bytes().to_vec(),
+            }),
+        ))
+    }
+
+    /// The index of the replacement to make at the current position, if any.
+    fn find_replacement(&self) -> Option<usize> {
+        if !self.replacement_chars[self.input[self.pos] as usize]
+            || self.options.ext_autolink && self.in_url()
+        {
+            return None;
+        }
+
+        self.options.ext_replacements.iter().position(|replacement| {
+            let original = replacement.0.as_bytes();
+            if !is_replaceable(original) || !self.input[self.pos..].starts_with(original) {
+                return false;
+            }
+
+            // Replacements starting or ending with a letter or digit must do so at a word
+            // boundary, where a `/` also continues the word, so that neither `11/2` nor the date
+            // `1/2/2020` is taken as a fraction.
+            let end = self.pos + original.len();
+            let in_word = |c: u8| isalnum(c) || c == b'/';
+            if isalnum(original[0]) && self.pos > 0 && in_word(self.input[self.pos - 1]) {
+                return false;
+            }
+            !isalnum(original[original.len() - 1])
+                || end == self.input.len()
+                || !in_word(self.input[end])
+        })
+    }
+
+    /// Whether the autolink extension may turn the word the current position is in into a link,
+    /// so that the delimiters of the subscript, highlight and insert extensions are taken
+    /// literally.
+    fn in_autolink_candidate(&self) -> bool {
+        self.options.ext_autolink && self.in_url()
+    }
+
+    /// Whether the word the current position is in looks like a URL or an email address, which
+    /// may be turned into a link by the autolink extension.  Punctuation such as `(` or `[`
+    /// before the URL isn't part of the word.
+    fn in_url(&self) -> bool {
+        let mut start = self.input[..self.pos]
+            .iter()
+            .rposition(|&c| isspace(c))
+            .map_or(0, |i| i + 1);
+        while start < self.pos && ispunct(self.input[start]) {
+            start += 1;
+        }
+        let end = self.input[self.pos..]
+            .iter()
+            .position(|&c| isspace(c))
+            .map_or(self.input.len(), |i| self.pos + i);
+        let word = &self.input[start..self.pos];
+        self.input[start..end].contains(&b'@')
+            || word.starts_with(b"www.")
+            || word.windows(3).any(|w| w == b"://")
+            || self.options
+                .ext_autolink_schemes
+                .iter()
+                .any(|scheme| word.starts_with(scheme.as_bytes()))
+    }
+
     pub fn handle_period(&mut self) -> &'a AstNode<'a> {
         self.pos += 1;
-        if self.options.smart && self.peek_char().map_or(false, |&c| c == b'.') {
+        if self.options.smart
+            && self.options.smart_punctuation.ellipses
+            && self.peek_char().map_or(false, |&c| c == b'.')
+        {
             self.pos += 1;
             if self.peek_char().map_or(false, |&c| c == b'.') {
                 self.pos += 1;
@@ -679,6 +838,25 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         }
     }
 
+    /// Whether the runs of `opener` and `closer`, which use the same character, are of lengths
+    /// that can be paired.  With the subscript extension a single `~` is subscript and a double
+    /// `~~` strikethrough, so the runs must be the same length; highlights, insertions and
+    /// spoilers take exactly two characters.
+    fn delimiter_lengths_match(
+        &self,
+        opener: &Delimiter<'a, 'd>,
+        closer: &Delimiter<'a, 'd>,
+    ) -> bool {
+        match opener.delim_char {
+            b'~' if self.options.ext_subscript => {
+                opener.length == closer.length
+                    && (opener.length == 1 || self.options.ext_strikethrough)
+            }
+            b'=' | b'+' | b'|' => opener.length == 2 && closer.length == 2,
+            _ => true,
+        }
+    }
+
     pub fn push_delimiter(&mut self, c: u8, can_open: bool, can_close: bool, inl: &'a AstNode<'a>) {
         let d = self.delimiter_arena.alloc(Delimiter {
             prev: Cell::new(self.last_delimiter),
@@ -717,7 +895,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         opener_num_chars -= use_delims;
         closer_num_chars -= use_delims;
 
-        if self.options.ext_strikethrough && opener_char == b'~' {
+        if (self.options.ext_strikethrough || self.options.ext_subscript) && opener_char == b'~' {
             opener_num_chars = 0;
             closer_num_chars = 0;
         }
@@ -749,10 +927,20 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
 
         let emph = make_inline(
             self.arena,
-            if self.options.ext_strikethrough && opener_char == b'~' {
+            if self.options.ext_subscript && opener_char == b'~' && opener.length == 1 {
+                NodeValue::Subscript
+            } else if self.options.ext_strikethrough && opener_char == b'~' {
                 NodeValue::Strikethrough
             } else if self.options.ext_superscript && opener_char == b'^' {
                 NodeValue::Superscript
+            } else if self.options.ext_highlight && opener_char == b'=' {
+                NodeValue::Highlight
+            } else if self.options.ext_insert && opener_char == b'+' {
+                NodeValue::Insert
+            } else if self.options.ext_spoiler && opener_char == b'|' {
+                NodeValue::Spoiler
+            } else if self.options.ext_underline && opener_char == b'_' && use_delims == 2 {
+                NodeValue::Underline
             } else if use_delims == 1 {
                 NodeValue::Emph
             } else {
@@ -869,6 +1057,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             inl_text: inl_text,
             position: self.pos,
             image: image,
+            inline_footnote: false,
             active: true,
             bracket_after: false,
         });
@@ -888,6 +1077,34 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             return Some(make_inline(self.arena, NodeValue::Text(b"]".to_vec())));
         }
 
+        let opener = self.brackets[brackets_len - 1].inl_text;
+        if self.brackets[brackets_len - 1].inline_footnote {
+            if opener.next_sibling().is_none() {
+                self.brackets.pop();
+                return Some(make_inline(self.arena, NodeValue::Text(b"]".to_vec())));
+            }
+
+            // The footnote's content is kept as the reference's children until
+            // `Parser::process_footnotes` moves it into a definition.
+            let inl = make_inline(
+                self.arena,
+                NodeValue::FootnoteReference(NodeFootnoteReference {
+                    name: vec![],
+                    ix: 0,
+                    ref_num: 0,
+                }),
+            );
+            opener.insert_before(inl);
+            while let Some(tmp) = opener.next_sibling() {
+                inl.append(tmp);
+            }
+            opener.detach();
+            let previous_delimiter = self.brackets[brackets_len - 1].previous_delimiter;
+            self.process_emphasis(previous_delimiter);
+            self.brackets.pop();
+            return None;
+        }
+
         let is_image = self.brackets[brackets_len - 1].image;
         let after_link_text_pos = self.pos;
 
@@ -960,7 +1177,14 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
             } {
             let text = text.unwrap();
             if text.len() > 1 && text[0] == b'^' {
-                let inl = make_inline(self.arena, NodeValue::FootnoteReference(text[1..].to_vec()));
+                let inl = make_inline(
+                    self.arena,
+                    NodeValue::FootnoteReference(NodeFootnoteReference {
+                        name: text[1..].to_vec(),
+                        ix: 0,
+                        ref_num: 0,
+                    }),
+                );
                 self.brackets[brackets_len - 1].inl_text.insert_before(inl);
                 self.brackets[brackets_len - 1]
                     .inl_text
@@ -1010,7 +1234,7 @@ impl<'a, 'r, 'o, 'd, 'i> Subject<'a, 'r, 'o, 'd, 'i> {
         if !is_image {
             let mut i = brackets_len as i32 - 1;
             while i >= 0 {
-                if !self.brackets[i as usize].image {
+                if !self.brackets[i as usize].image && !self.brackets[i as usize].inline_footnote {
                     if !self.brackets[i as usize].active {
                         break;
                     } else {
@@ -1138,6 +1362,8 @@ pub fn make_inline<'a>(arena: &'a Arena<AstNode<'a>>, value: NodeValue) -> &'a A
         value: value,
         content: vec![],
         start_line: 0,
+        end_line: 0,
+        start_column: 0,
         open: false,
         last_line_blank: false,
     };
@@ -1,24 +1,32 @@
+mod abbreviations;
 mod autolink;
 mod inlines;
+mod references;
 mod table;
 
 use arena_tree::Node;
 use ctype::{isdigit, isspace};
 use entity;
+use html::FootnoteHtml;
 use nodes;
 use nodes::{
     make_block, Ast, AstNode, ListDelimType, ListType, NodeCodeBlock, NodeDescriptionItem,
-    NodeHeading, NodeHtmlBlock, NodeList, NodeValue,
+    NodeContainer, NodeFootnoteDefinition, NodeHeading, NodeHtmlBlock, NodeList, NodeTaskItem,
+    NodeValue,
 };
 use regex::bytes::Regex;
+use sanitize::SanitizePolicy;
 use scanners;
 use std::cell::RefCell;
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::mem;
 use std::str;
+use std::sync::Arc;
 use strings;
 use typed_arena::Arena;
+use url_policy::UrlPolicy;
 
 const TAB_STOP: usize = 4;
 const CODE_INDENT: usize = 4;
@@ -39,22 +47,195 @@ pub fn parse_document<'a>(
     arena: &'a Arena<AstNode<'a>>,
     buffer: &str,
     options: &ComrakOptions,
+) -> &'a AstNode<'a> {
+    parse_document_with_references(arena, buffer, options, &mut ReferenceMap::default())
+}
+
+/// Parse a Markdown document to an AST, resolving links with the reference definitions in
+/// `refmap` as well as its own.  The document's own definitions are added to `refmap`, so that
+/// it can be shared with fragments parsed by `parse_fragment` and `parse_inline`.  Where a
+/// label is defined more than once, the first definition wins.
+pub fn parse_document_with_references<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &ComrakOptions,
+    refmap: &mut ReferenceMap,
+) -> &'a AstNode<'a> {
+    let root = parse_blocks(arena, buffer, options, refmap);
+    for transform in &options.transforms {
+        transform(arena, root);
+    }
+    root
+}
+
+/// Parse a fragment of Markdown and append the resulting blocks to `parent`, which may be in
+/// an existing document.  Links are resolved with the reference definitions in `refmap`, if
+/// given, and the fragment's own definitions are added to it.  The `transforms` option is not
+/// applied.
+///
+/// If `parent` cannot contain one of the blocks, nothing is appended, no definitions are added
+/// and an error is returned.
+///
+/// ```
+/// # use comrak::{format_html, parse_document_with_references, parse_fragment, Arena,
+/// #              ComrakOptions, ReferenceMap};
+/// let arena = Arena::new();
+/// let options = ComrakOptions::default();
+/// let mut refmap = ReferenceMap::default();
+/// let root = parse_document_with_references(
+///     &arena, "> Quote\n\n[home]: https://example.com\n", &options, &mut refmap);
+///
+/// let quote = root.first_child().unwrap();
+/// parse_fragment(&arena, quote, "* A [home] link\n", &options, Some(&mut refmap)).unwrap();
+///
+/// let mut html = vec![];
+/// format_html(root, &options, &mut html).unwrap();
+/// assert_eq!(String::from_utf8(html).unwrap(),
+///            "<blockquote>\n<p>Quote</p>\n<ul>\n\
+///             <li>A <a href=\"https://example.com\">home</a> link</li>\n\
+///             </ul>\n</blockquote>\n");
+/// ```
+pub fn parse_fragment<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    parent: &'a AstNode<'a>,
+    buffer: &str,
+    options: &ComrakOptions,
+    refmap: Option<&mut ReferenceMap>,
+) -> Result<(), nodes::InvalidChild> {
+    // The fragment's definitions are only kept if its blocks are.
+    let mut fragment_refmap = refmap.as_ref().map_or_else(ReferenceMap::default, |r| (*r).clone());
+    let root = parse_blocks(arena, buffer, options, &mut fragment_refmap);
+    append_children(parent, root)?;
+    if let Some(refmap) = refmap {
+        *refmap = fragment_refmap;
+    }
+    Ok(())
+}
+
+/// Parse a string of inline Markdown, such as a title, and append the resulting inlines to
+/// `parent` without wrapping them in a paragraph.  Links are resolved with the reference
+/// definitions in `refmap`, if given; definitions can't appear in inline Markdown.
+///
+/// If `parent` cannot contain inlines, nothing is appended and an error is returned.
+///
+/// ```
+/// # use comrak::{format_html, parse_inline, Arena, ComrakOptions};
+/// # use comrak::nodes::{new_node, NodeHeading, NodeValue};
+/// let arena = Arena::new();
+/// let options = ComrakOptions::default();
+/// let root = new_node(&arena, NodeValue::Document);
+/// let heading = new_node(&arena, NodeValue::Heading(NodeHeading { level: 1, setext: false }));
+/// root.append(heading);
+/// parse_inline(&arena, heading, "Release *1.0*", &options, None).unwrap();
+///
+/// let mut html = vec![];
+/// format_html(root, &options, &mut html).unwrap();
+/// assert_eq!(String::from_utf8(html).unwrap(), "<h1>Release <em>1.0</em></h1>\n");
+/// ```
+pub fn parse_inline<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    parent: &'a AstNode<'a>,
+    buffer: &str,
+    options: &ComrakOptions,
+    refmap: Option<&mut ReferenceMap>,
+) -> Result<(), nodes::InvalidChild> {
+    let container = nodes::new_node(arena, NodeValue::Paragraph);
+    container.data.borrow_mut().content = buffer.as_bytes().to_vec();
+
+    let mut parser = Parser::new(arena, container, options);
+    let mut empty = ReferenceMap::default();
+    let refmap = refmap.unwrap_or(&mut empty);
+    mem::swap(&mut parser.refmap, &mut refmap.map);
+    parser.parse_inlines(container);
+    parser.postprocess_text_nodes(container);
+    mem::swap(&mut parser.refmap, &mut refmap.map);
+
+    append_children(parent, container)
+}
+
+fn parse_blocks<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &ComrakOptions,
+    refmap: &mut ReferenceMap,
 ) -> &'a AstNode<'a> {
     let root: &'a AstNode<'a> = arena.alloc(Node::new(RefCell::new(Ast {
         value: NodeValue::Document,
         content: vec![],
         start_line: 0,
+        end_line: 0,
+        start_column: 0,
         open: true,
         last_line_blank: false,
     })));
     let mut parser = Parser::new(arena, root, options);
+    mem::swap(&mut parser.refmap, &mut refmap.map);
     parser.feed(buffer);
-    parser.finish()
+    parser.finish();
+    mem::swap(&mut parser.refmap, &mut refmap.map);
+    root
+}
+
+/// Moves the children of `from` to the end of `parent`, if it can contain all of them.
+fn append_children<'a>(
+    parent: &'a AstNode<'a>,
+    from: &'a AstNode<'a>,
+) -> Result<(), nodes::InvalidChild> {
+    for child in from.children() {
+        let child = &child.data.borrow().value;
+        if !nodes::can_contain_type(parent, child) {
+            return Err(nodes::InvalidChild {
+                parent: parent.data.borrow().value.clone(),
+                child: child.clone(),
+            });
+        }
+    }
+    while let Some(child) = from.first_child() {
+        parent.append(child);
+    }
+    Ok(())
+}
+
+/// Link reference definitions, shared between a document and fragments parsed into it.
+#[derive(Debug, Default, Clone)]
+pub struct ReferenceMap {
+    map: HashMap<Vec<u8>, Reference>,
+}
+
+impl ReferenceMap {
+    /// Adds a definition for `label`, unless it's already defined.
+    pub fn insert(&mut self, label: &str, url: &str, title: &str) {
+        let label = strings::normalize_label(label.as_bytes());
+        if !label.is_empty() {
+            self.map.entry(label).or_insert(Reference {
+                url: url.as_bytes().to_vec(),
+                title: title.as_bytes().to_vec(),
+            });
+        }
+    }
+
+    /// Returns whether `label` is defined.  Labels are compared case-insensitively, with
+    /// whitespace collapsed.
+    pub fn contains(&self, label: &str) -> bool {
+        self.map
+            .contains_key(&strings::normalize_label(label.as_bytes()))
+    }
+
+    /// Returns the number of definitions.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether there are no definitions.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
 }
 
 pub struct Parser<'a, 'o> {
     arena: &'a Arena<AstNode<'a>>,
     refmap: HashMap<Vec<u8>, Reference>,
+    abbreviations: HashMap<Vec<u8>, Vec<u8>>,
     root: &'a AstNode<'a>,
     current: &'a AstNode<'a>,
     line_number: u32,
@@ -66,10 +247,26 @@ pub struct Parser<'a, 'o> {
     blank: bool,
     partially_consumed_tab: bool,
     last_line_length: usize,
+    end_of_input: bool,
     options: &'o ComrakOptions,
 }
 
-#[derive(Default, Debug, Clone)]
+/// The type of the `url_rewriter` option.
+pub type UrlRewriter = Arc<for<'a> Fn(&'a AstNode<'a>, &str) -> String + Send + Sync>;
+
+/// The type of each of the `transforms` option's functions.
+pub type Transform = Arc<for<'a> Fn(&'a Arena<AstNode<'a>>, &'a AstNode<'a>) + Send + Sync>;
+
+/// The type of the `ext_autolink_filter` option.
+pub type AutolinkFilter = Arc<Fn(&str) -> Option<String> + Send + Sync>;
+
+/// The type of the `ext_reference_resolver` option.
+pub type ReferenceResolver = Arc<Fn(ReferenceKind, &str) -> Option<String> + Send + Sync>;
+
+/// The type of the `ext_container_html` option.
+pub type ContainerHtml = Arc<Fn(&NodeContainer, bool) -> Option<String> + Send + Sync>;
+
+#[derive(Default, Clone)]
 /// Options for both parser and formatter functions.
 pub struct ComrakOptions {
     /// [Soft line breaks](http://spec.commonmark.org/0.27/#soft-line-breaks) in the input
@@ -101,6 +298,25 @@ pub struct ComrakOptions {
     /// ```
     pub smart: bool,
 
+    /// Which smart punctuation is used when `smart` is set: the style of quotation marks, and
+    /// whether dashes and ellipses are converted.  See `SmartPunctuation` for details.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, QuoteStyle, SmartPunctuation};
+    /// let options = ComrakOptions {
+    ///   smart: true,
+    ///   smart_punctuation: SmartPunctuation {
+    ///     quotes: QuoteStyle::German,
+    ///     ellipses: false,
+    ///     ..SmartPunctuation::default()
+    ///   },
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("\"Hallo,\" sagte sie -- und ging...", &options),
+    ///            "<p>„Hallo,“ sagte sie – und ging...</p>\n");
+    /// ```
+    pub smart_punctuation: SmartPunctuation,
+
     /// GitHub-style `<pre lang="xyz">` is used for fenced code blocks with info tags.
     ///
     /// ```
@@ -139,6 +355,178 @@ pub struct ComrakOptions {
     /// ```
     pub width: usize,
 
+    /// Pad table cells to their column's display width when outputting CommonMark, and write
+    /// the delimiter row to match.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// options.ext_table = true;
+    /// let node = parse_document(&arena, "| a | bcd |\n|:-|-:|\n| ef | g |\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(),
+    ///            "| a | bcd |\n| :-- | --: |\n| ef | g |\n");
+    ///
+    /// options.align_tables = true;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(),
+    ///            "| a   | bcd |\n| :-- | --: |\n| ef  |   g |\n");
+    /// # }
+    /// ```
+    pub align_tables: bool,
+
+    /// The bullet character used for bullet list items when outputting CommonMark.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, ListStyleType, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "* a\n* b\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "  - a\n  - b\n");
+    ///
+    /// options.list_style = ListStyleType::Star;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "  * a\n  * b\n");
+    /// # }
+    /// ```
+    pub list_style: ListStyleType,
+
+    /// How ordered list items are numbered when outputting CommonMark.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, OrderedListNumbering, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "1. a\n1. b\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "1.  a\n2.  b\n");
+    ///
+    /// options.ordered_list_numbering = OrderedListNumbering::Constant;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "1.  a\n1.  b\n");
+    /// # }
+    /// ```
+    pub ordered_list_numbering: OrderedListNumbering,
+
+    /// The delimiter used for emphasis when outputting CommonMark.  Underscores are only used
+    /// where they would delimit the same emphasis when parsed again, i.e. not within words.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, EmphasisDelimiter, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "*a* b*c*d\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "*a* b*c*d\n");
+    ///
+    /// options.emph_delimiter = EmphasisDelimiter::Underscore;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "_a_ b*c*d\n");
+    /// # }
+    /// ```
+    pub emph_delimiter: EmphasisDelimiter,
+
+    /// The delimiter used for strong emphasis when outputting CommonMark.  As with
+    /// `emph_delimiter`, underscores are not used within words.
+    pub strong_delimiter: EmphasisDelimiter,
+
+    /// Whether level 1 and 2 headings are written as ATX or setext headings when outputting
+    /// CommonMark.  Headings of other levels are always written as ATX headings.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, HeadingStyle, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "# Title\n\n### Section\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "# Title\n\n### Section\n");
+    ///
+    /// options.heading_style = HeadingStyle::Setext;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "Title\n=====\n\n### Section\n");
+    /// # }
+    /// ```
+    pub heading_style: HeadingStyle,
+
+    /// The character used for code fences when outputting CommonMark.  Backtick fences are
+    /// replaced by tilde fences where the info string contains a backtick.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, CodeFenceChar, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "``` rust\nfn main() {}\n```\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "``` rust\nfn main() {}\n```\n");
+    ///
+    /// options.code_fence_char = CodeFenceChar::Tilde;
+    /// options.code_fence_length = 4;
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "~~~~ rust\nfn main() {}\n~~~~\n");
+    /// # }
+    /// ```
+    pub code_fence_char: CodeFenceChar,
+
+    /// The minimum length of code fences when outputting CommonMark.  Values below 3 are
+    /// treated as 3.  Fences are made longer where the code itself contains a run of the fence
+    /// character.
+    pub code_fence_length: usize,
+
+    /// The string written for thematic breaks when outputting CommonMark.  If unset, or not a
+    /// valid thematic break, `-----` is used.
+    ///
+    /// ```
+    /// # extern crate typed_arena;
+    /// # extern crate comrak;
+    /// # use comrak::{parse_document, ComrakOptions, format_commonmark};
+    /// # fn main() {
+    /// # let arena = typed_arena::Arena::new();
+    /// let mut options = ComrakOptions::default();
+    /// let node = parse_document(&arena, "a\n\n***\n\nb\n", &options);
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "a\n\n-----\n\nb\n");
+    ///
+    /// options.thematic_break = Some("* * *".to_string());
+    /// let mut output = vec![];
+    /// format_commonmark(node, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "a\n\n* * *\n\nb\n");
+    /// # }
+    /// ```
+    pub thematic_break: Option<String>,
+
     /// The default info string for fenced code blocks.
     ///
     /// ```
@@ -178,6 +566,100 @@ pub struct ComrakOptions {
     /// ```
     pub safe: bool,
 
+    /// Sanitize raw HTML against an allowlist of tags, attributes and URL schemes, rather than
+    /// passing it through.  Has no effect if `safe` is set, as raw HTML is then omitted.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, SanitizePolicy};
+    /// let options = ComrakOptions {
+    ///   sanitize: Some(SanitizePolicy::default()),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// let input = "<details onclick=\"x()\" open><summary>More</summary>\n\n\
+    ///              Press <kbd style=\"color: red\">Enter</kbd> or <a href=\"javascript:x()\">here</a>.\n\n\
+    ///              </details>\n";
+    /// assert_eq!(markdown_to_html(input, &options),
+    ///            "<details open><summary>More</summary>\n\
+    ///             <p>Press <kbd>Enter</kbd> or <a>here</a>.</p>\n\
+    ///             </details>\n");
+    /// ```
+    pub sanitize: Option<SanitizePolicy>,
+
+    /// Rules applied to link and image URLs in HTML output: allowed schemes and image hosts,
+    /// resolution of relative URLs, attributes for external links and proxying of images.  See
+    /// `UrlPolicy` for details.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, UrlPolicy};
+    /// let options = ComrakOptions {
+    ///   url_policy: Some(UrlPolicy {
+    ///     external_link_rel: Some("nofollow".to_string()),
+    ///     ..UrlPolicy::default()
+    ///   }),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("[Home](/) [Away](http://example.com)\n", &options),
+    ///            "<p><a href=\"/\">Home</a> <a href=\"http://example.com\" rel=\"nofollow\">Away</a></p>\n");
+    /// ```
+    pub url_policy: Option<UrlPolicy>,
+
+    /// A function called by the HTML formatter with each `Link` and `Image` node and its URL,
+    /// which returns the URL to output.  The `safe` and `url_policy` options are applied to the
+    /// URL it returns.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// # use comrak::nodes::{AstNode, NodeValue};
+    /// # use std::sync::Arc;
+    /// let mount = "/docs/v2".to_string();
+    /// let rewrite = move |node: &AstNode, url: &str| match node.data.borrow().value {
+    ///     NodeValue::Link(..) if url.ends_with(".md") => {
+    ///         format!("{}/{}/", mount, url.trim_start_matches("../").trim_end_matches(".md"))
+    ///     }
+    ///     NodeValue::Image(..) => format!("/assets/{}", url),
+    ///     _ => url.to_string(),
+    /// };
+    ///
+    /// let options = ComrakOptions {
+    ///   url_rewriter: Some(Arc::new(rewrite)),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("[API](../api/foo.md) ![logo](logo.png)\n", &options),
+    ///            "<p><a href=\"/docs/v2/api/foo/\">API</a> \
+    ///             <img src=\"/assets/logo.png\" alt=\"logo\" /></p>\n");
+    /// ```
+    pub url_rewriter: Option<UrlRewriter>,
+
+    /// Functions run in order on the document by `parse_document` once it has been parsed, so
+    /// that their changes are seen by every formatter.  They're given the arena the document
+    /// was allocated in, for creating new nodes.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, Arena, ComrakOptions};
+    /// # use comrak::nodes::{AstNode, NodeValue};
+    /// # use std::sync::Arc;
+    /// fn drop_images<'a>(_arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+    ///     let images = root
+    ///         .descendants()
+    ///         .filter(|n| match n.data.borrow().value {
+    ///             NodeValue::Image(..) => true,
+    ///             _ => false,
+    ///         })
+    ///         .collect::<Vec<_>>();
+    ///     for image in images {
+    ///         image.detach();
+    ///     }
+    /// }
+    ///
+    /// let options = ComrakOptions {
+    ///   transforms: vec![Arc::new(drop_images)],
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Look: ![a cat](cat.png)\n", &options),
+    ///            "<p>Look: </p>\n");
+    /// ```
+    pub transforms: Vec<Transform>,
+
     /// Enables the
     /// [strikethrough extension](https://github.github.com/gfm/#strikethrough-extension-)
     /// from the GFM spec.
@@ -237,12 +719,142 @@ pub struct ComrakOptions {
     /// ```
     pub ext_autolink: bool,
 
+    /// The URL schemes recognised by the autolink extension, each given with the separator
+    /// that must follow it.  A scheme ending in `://` must be followed by a domain; any other
+    /// scheme, such as `jira:`, may be followed by anything up to the next space.  If empty,
+    /// `http://`, `https://` and `ftp://` are recognised.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_autolink: true,
+    ///   ext_autolink_schemes: vec!["https://".to_string(), "jira:".to_string()],
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("See jira:PROJ-123, not ftp://x.org.\n", &options),
+    ///            "<p>See <a href=\"jira:PROJ-123\">jira:PROJ-123</a>, not ftp://x.org.</p>\n");
+    /// ```
+    pub ext_autolink_schemes: Vec<String>,
+
+    /// Relaxes the domain checks of the autolink extension, so that URLs with hostnames
+    /// without dots or containing underscores are recognised.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions {
+    ///   ext_autolink: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("http://wiki/Home\n", &options),
+    ///            "<p>http://wiki/Home</p>\n");
+    ///
+    /// options.ext_autolink_relaxed = true;
+    /// assert_eq!(markdown_to_html("http://wiki/Home\n", &options),
+    ///            "<p><a href=\"http://wiki/Home\">http://wiki/Home</a></p>\n");
+    /// ```
+    pub ext_autolink_relaxed: bool,
+
+    /// A function called with the URL of each link the autolink extension finds.  It may return
+    /// `None` to leave the text unlinked, or the URL to link to, which may be rewritten.  The
+    /// link text is always the text as written.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// # use std::sync::Arc;
+    /// fn filter(url: &str) -> Option<String> {
+    ///     if url.contains("example.org") {
+    ///         None
+    ///     } else {
+    ///         Some(url.replace("http://", "https://"))
+    ///     }
+    /// }
+    ///
+    /// let options = ComrakOptions {
+    ///   ext_autolink: true,
+    ///   ext_autolink_filter: Some(Arc::new(filter)),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("www.example.com www.example.org\n", &options),
+    ///            "<p><a href=\"https://www.example.com\">www.example.com</a> www.example.org</p>\n");
+    /// ```
+    pub ext_autolink_filter: Option<AutolinkFilter>,
+
+    /// Links issue references such as `#123` to the URL given by this template, in which `{}`
+    /// is replaced by the issue number.
+    ///
+    /// References are found in text only, never in code or inside existing links.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_issue_url: Some("https://tracker.example.com/issues/{}".to_string()),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Fixes #123, not `#124`.\n", &options),
+    ///            "<p>Fixes <a href=\"https://tracker.example.com/issues/123\">#123</a>, \
+    ///             not <code>#124</code>.</p>\n");
+    /// ```
+    pub ext_issue_url: Option<String>,
+
+    /// Links user mentions such as `@octocat` to the URL given by this template, in which `{}`
+    /// is replaced by the user name.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_mention_url: Some("https://example.com/{}".to_string()),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Thanks @octocat!\n", &options),
+    ///            "<p>Thanks <a href=\"https://example.com/octocat\">@octocat</a>!</p>\n");
+    /// ```
+    pub ext_mention_url: Option<String>,
+
+    /// Links commit SHAs (7 to 40 lowercase hexadecimal digits, including at least one decimal
+    /// digit) to the URL given by this template, in which `{}` is replaced by the SHA.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_commit_url: Some("https://example.com/commit/{}".to_string()),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Reverts 3f2a9c1.\n", &options),
+    ///            "<p>Reverts <a href=\"https://example.com/commit/3f2a9c1\">3f2a9c1</a>.</p>\n");
+    /// ```
+    pub ext_commit_url: Option<String>,
+
+    /// A function which resolves issue references, user mentions and commit SHAs to URLs.  It is
+    /// called with the kind of reference and the issue number, user name or SHA, and may return
+    /// `None` to leave the reference unlinked.  When set, all kinds of reference are detected,
+    /// and the URL templates are not used.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, ReferenceKind};
+    /// # use std::sync::Arc;
+    /// fn resolve(kind: ReferenceKind, id: &str) -> Option<String> {
+    ///     match kind {
+    ///         ReferenceKind::Issue => Some(format!("/issues/{}", id)),
+    ///         ReferenceKind::Mention if id != "nobody" => Some(format!("/users/{}", id)),
+    ///         _ => None,
+    ///     }
+    /// }
+    ///
+    /// let options = ComrakOptions {
+    ///   ext_reference_resolver: Some(Arc::new(resolve)),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("#1 @me @nobody\n", &options),
+    ///            "<p><a href=\"/issues/1\">#1</a> <a href=\"/users/me\">@me</a> @nobody</p>\n");
+    /// ```
+    pub ext_reference_resolver: Option<ReferenceResolver>,
+
     /// Enables the
     /// [task list items extension](https://github.github.com/gfm/#task-list-items-extension-)
     /// from the GFM spec.
     ///
-    /// Note that the spec does not define the precise output, so only the bare essentials are
-    /// rendered.
+    /// Each checkbox is parsed into a `TaskItem` node.  Note that the spec does not define the
+    /// precise output, so only the bare essentials are rendered.
     ///
     /// ```
     /// # use comrak::{markdown_to_html, ComrakOptions};
@@ -256,6 +868,23 @@ pub struct ComrakOptions {
     /// ```
     pub ext_tasklist: bool,
 
+    /// Renders the checkboxes of task list items enabled, rather than disabled, each with a
+    /// `data-task-index` attribute giving its index among the task list items of the document.
+    /// A script can use this to tick checkboxes in the source with `tasks::toggle_task`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_tasklist: true,
+    ///   ext_tasklist_interactive: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("* [x] Done\n* [ ] Not done\n", &options),
+    ///            "<ul>\n<li><input type=\"checkbox\" data-task-index=\"0\" checked=\"\" /> Done</li>\n\
+    ///            <li><input type=\"checkbox\" data-task-index=\"1\" /> Not done</li>\n</ul>\n");
+    /// ```
+    pub ext_tasklist_interactive: bool,
+
     /// Enables the superscript Comrak extension.
     ///
     /// ```
@@ -269,6 +898,144 @@ pub struct ComrakOptions {
     /// ```
     pub ext_superscript: bool,
 
+    /// Enables the subscript extension, written `~text~`.  If `ext_strikethrough` is also
+    /// enabled, strikethrough must then be written with two tildes, `~~text~~`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_subscript: true,
+    ///   ext_strikethrough: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("H~2~O is ~~not~~ water.\n", &options),
+    ///            "<p>H<sub>2</sub>O is <del>not</del> water.</p>\n");
+    /// ```
+    pub ext_subscript: bool,
+
+    /// Enables the highlight extension, written `==text==`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_highlight: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("A ==key== point.\n", &options),
+    ///            "<p>A <mark>key</mark> point.</p>\n");
+    /// ```
+    pub ext_highlight: bool,
+
+    /// Enables the insert extension, written `++text++`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_insert: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("A ++new++ point.\n", &options),
+    ///            "<p>A <ins>new</ins> point.</p>\n");
+    /// ```
+    pub ext_insert: bool,
+
+    /// Enables the underline extension, which interprets `__text__` as underlined rather than
+    /// strongly emphasised.  `**text**` is still strongly emphasised.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_underline: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("__Underlined__ and **strong**.\n", &options),
+    ///            "<p><u>Underlined</u> and <strong>strong</strong>.</p>\n");
+    /// ```
+    pub ext_underline: bool,
+
+    /// Enables the spoiler extension, which hides text until the reader reveals it.  Inline
+    /// spoilers are written `||text||`, and block spoilers by starting each line with `>!`.  A
+    /// line ending with `!<` ends the block spoiler, and `>![` still starts a block quote.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_spoiler: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("It was ||the butler||.\n\n>! He did it. !<\n", &options),
+    ///            "<p>It was <span class=\"spoiler\">the butler</span>.</p>\n\
+    ///             <details class=\"spoiler\">\n<p>He did it.</p>\n</details>\n");
+    /// ```
+    pub ext_spoiler: bool,
+
+    /// Enables containers fenced by lines of at least three colons, as in
+    /// [markdown-it](https://github.com/markdown-it/markdown-it-container) and
+    /// [Pandoc](https://pandoc.org/MANUAL.html#divs-and-spans).  The opening fence is followed
+    /// by a name, attributes in braces, or both, and then optionally a title.  Containers nest,
+    /// and a closing fence closes the innermost container it's long enough for.  In HTML, the
+    /// attributes other than classes are filtered by the `sanitize` policy for a `<div>`, if set.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_container: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("::: warning {#backup}\nBack up *first*.\n:::\n", &options),
+    ///            "<div class=\"warning\" id=\"backup\">\n\
+    ///             <p>Back up <em>first</em>.</p>\n</div>\n");
+    /// ```
+    pub ext_container: bool,
+
+    /// A function called by the HTML formatter on entering and on leaving each `Container`
+    /// node, which returns the HTML to write, or `None` to write the default `<div>` with the
+    /// container's name as its class.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// # use comrak::nodes::NodeContainer;
+    /// # use std::sync::Arc;
+    /// fn render(container: &NodeContainer, entering: bool) -> Option<String> {
+    ///     if container.name != b"tip" {
+    ///         None
+    ///     } else if entering {
+    ///         Some(format!("<aside class=\"tip\">\n<p class=\"title\">{}</p>\n",
+    ///                      String::from_utf8_lossy(&container.title)))
+    ///     } else {
+    ///         Some("</aside>\n".to_string())
+    ///     }
+    /// }
+    ///
+    /// let options = ComrakOptions {
+    ///   ext_container: true,
+    ///   ext_container_html: Some(Arc::new(render)),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("::: tip Did you know?\nIt nests.\n:::\n", &options),
+    ///            "<aside class=\"tip\">\n<p class=\"title\">Did you know?</p>\n\
+    ///             <p>It nests.</p>\n</aside>\n");
+    /// ```
+    pub ext_container_html: Option<ContainerHtml>,
+
+    /// Enables [PHP Markdown Extra](https://michelf.ca/projects/php-markdown/extra/#abbr)-style
+    /// abbreviations.  A line `*[text]: title` at the start of a paragraph defines an
+    /// abbreviation, which is then marked wherever `text` appears as a whole word in the
+    /// document, including in the text of links.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_abbreviations: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Use HTML, not HTML5.\n\n*[HTML]: HyperText Markup Language\n",
+    ///                             &options),
+    ///            "<p>Use <abbr title=\"HyperText Markup Language\">HTML</abbr>, \
+    ///             not HTML5.</p>\n");
+    /// ```
+    pub ext_abbreviations: bool,
+
     /// Enables the header IDs Comrak extension.
     ///
     /// ```
@@ -294,9 +1061,49 @@ pub struct ComrakOptions {
     ///   ..ComrakOptions::default()
     /// };
     /// assert_eq!(markdown_to_html("Hi[^x].\n\n[^x]: A greeting.\n", &options),
-    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup>.</p>\n<section class=\"footnotes\">\n<ol>\n<li id=\"fn1\">\n<p>A greeting. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n</li>\n</ol>\n</section>\n");
+    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup>.</p>\n<section class=\"footnotes\">\n<ol>\n<li id=\"fn1\">\n<p>A greeting. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n</li>\n</ol>\n</section>\n");
+    /// ```
+    pub ext_footnotes: bool,
+
+    /// Enables [Pandoc](https://pandoc.org/MANUAL.html#extension-inline_notes)-style inline
+    /// footnotes, written `^[text]`, when `ext_footnotes` is also enabled.  They're formatted
+    /// as CommonMark as ordinary footnotes.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_footnotes: true,
+    ///   ext_inline_footnotes: true,
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Hi^[A *greeting*.].\n", &options),
+    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup>.</p>\n\
+    ///             <section class=\"footnotes\">\n<ol>\n<li id=\"fn1\">\n\
+    ///             <p>A <em>greeting</em>. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n\
+    ///             </li>\n</ol>\n</section>\n");
+    /// ```
+    pub ext_inline_footnotes: bool,
+
+    /// How footnotes are written in HTML: a prefix for their IDs, the section they're written
+    /// in, and the content and attributes of their links.  See `FootnoteHtml` for details.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, FootnoteHtml};
+    /// let options = ComrakOptions {
+    ///   ext_footnotes: true,
+    ///   ext_footnote_html: FootnoteHtml {
+    ///     id_prefix: "post-7-".to_string(),
+    ///     ..FootnoteHtml::default()
+    ///   },
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("Hi[^x].\n\n[^x]: A greeting.\n", &options),
+    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#post-7-fn1\" id=\"post-7-fnref1\">1</a></sup>.</p>\n\
+    ///             <section class=\"footnotes\">\n<ol>\n<li id=\"post-7-fn1\">\n\
+    ///             <p>A greeting. <a href=\"#post-7-fnref1\" class=\"footnote-backref\">↩</a></p>\n\
+    ///             </li>\n</ol>\n</section>\n");
     /// ```
-    pub ext_footnotes: bool,
+    pub ext_footnote_html: FootnoteHtml,
 
     /// Enables the description lists extension.
     ///
@@ -325,9 +1132,314 @@ pub struct ComrakOptions {
     ///            "<dl><dt>\n<p>Term</p>\n</dt>\n<dd>\n<p>Definition</p>\n</dd>\n</dl>\n");
     /// ```
     pub ext_description_lists: bool,
+
+    /// Enables typographic replacements: each occurrence in text of the first string of a pair
+    /// is replaced with the second, such as `(c)` with `©`.  Where several match, the first
+    /// in the list is used.  Replacements are never made in code, raw HTML or URLs, and those
+    /// starting or ending with a letter or digit are only made at word boundaries.  Those
+    /// starting with a character that may begin other syntax, one of ``\`&<[]!*_'"``, are
+    /// ignored.
+    /// `typographic_replacements` returns a common set.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, typographic_replacements, ComrakOptions};
+    /// let options = ComrakOptions {
+    ///   ext_replacements: typographic_replacements(),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// assert_eq!(markdown_to_html("(c) 2018 -> 1/2 +-5 `(tm)`\n", &options),
+    ///            "<p>© 2018 → ½ ±5 <code>(tm)</code></p>\n");
+    /// ```
+    pub ext_replacements: Vec<(String, String)>,
+
+    /// Writes typographic replacements made by `ext_replacements` as they were originally
+    /// written when formatting CommonMark, rather than as their replacements.
+    ///
+    /// ```
+    /// # use comrak::{format_commonmark, parse_document, typographic_replacements, Arena,
+    /// #              ComrakOptions};
+    /// let mut options = ComrakOptions {
+    ///   ext_replacements: typographic_replacements(),
+    ///   ..ComrakOptions::default()
+    /// };
+    /// let arena = Arena::new();
+    /// let root = parse_document(&arena, "(c) Me\n", &options);
+    ///
+    /// let mut output = vec![];
+    /// format_commonmark(root, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "© Me\n");
+    ///
+    /// options.ext_replacements_original = true;
+    /// let mut output = vec![];
+    /// format_commonmark(root, &options, &mut output).unwrap();
+    /// assert_eq!(String::from_utf8(output).unwrap(), "(c) Me\n");
+    /// ```
+    pub ext_replacements_original: bool,
+}
+
+// The functions set as options can't be shown, so are shown as `Function`.
+impl fmt::Debug for ComrakOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        #[derive(Debug, Clone)]
+        struct Function;
+
+        f.debug_struct("ComrakOptions")
+            .field("hardbreaks", &self.hardbreaks)
+            .field("smart", &self.smart)
+            .field("smart_punctuation", &self.smart_punctuation)
+            .field("github_pre_lang", &self.github_pre_lang)
+            .field("width", &self.width)
+            .field("align_tables", &self.align_tables)
+            .field("list_style", &self.list_style)
+            .field("ordered_list_numbering", &self.ordered_list_numbering)
+            .field("emph_delimiter", &self.emph_delimiter)
+            .field("strong_delimiter", &self.strong_delimiter)
+            .field("heading_style", &self.heading_style)
+            .field("code_fence_char", &self.code_fence_char)
+            .field("code_fence_length", &self.code_fence_length)
+            .field("thematic_break", &self.thematic_break)
+            .field("default_info_string", &self.default_info_string)
+            .field("safe", &self.safe)
+            .field("sanitize", &self.sanitize)
+            .field("url_policy", &self.url_policy)
+            .field("url_rewriter", &self.url_rewriter.as_ref().map(|_| Function))
+            .field("transforms", &vec![Function; self.transforms.len()])
+            .field("ext_strikethrough", &self.ext_strikethrough)
+            .field("ext_tagfilter", &self.ext_tagfilter)
+            .field("ext_table", &self.ext_table)
+            .field("ext_autolink", &self.ext_autolink)
+            .field("ext_autolink_schemes", &self.ext_autolink_schemes)
+            .field("ext_autolink_relaxed", &self.ext_autolink_relaxed)
+            .field("ext_autolink_filter", &self.ext_autolink_filter.as_ref().map(|_| Function))
+            .field("ext_issue_url", &self.ext_issue_url)
+            .field("ext_mention_url", &self.ext_mention_url)
+            .field("ext_commit_url", &self.ext_commit_url)
+            .field(
+                "ext_reference_resolver",
+                &self.ext_reference_resolver.as_ref().map(|_| Function),
+            )
+            .field("ext_tasklist", &self.ext_tasklist)
+            .field("ext_tasklist_interactive", &self.ext_tasklist_interactive)
+            .field("ext_superscript", &self.ext_superscript)
+            .field("ext_subscript", &self.ext_subscript)
+            .field("ext_highlight", &self.ext_highlight)
+            .field("ext_insert", &self.ext_insert)
+            .field("ext_underline", &self.ext_underline)
+            .field("ext_spoiler", &self.ext_spoiler)
+            .field("ext_container", &self.ext_container)
+            .field("ext_container_html", &self.ext_container_html.as_ref().map(|_| Function))
+            .field("ext_abbreviations", &self.ext_abbreviations)
+            .field("ext_header_ids", &self.ext_header_ids)
+            .field("ext_footnotes", &self.ext_footnotes)
+            .field("ext_inline_footnotes", &self.ext_inline_footnotes)
+            .field("ext_footnote_html", &self.ext_footnote_html)
+            .field("ext_description_lists", &self.ext_description_lists)
+            .field("ext_replacements", &self.ext_replacements)
+            .field("ext_replacements_original", &self.ext_replacements_original)
+            .finish()
+    }
+}
+
+/// A set of common typographic replacements for the `ext_replacements` option: `(c)`, `(r)`
+/// and `(tm)` in either case, `+-`, `->`, `=>`, the fractions `1/2`, `1/4` and `3/4`, and `...`.
+pub fn typographic_replacements() -> Vec<(String, String)> {
+    [
+        ("(c)", "©"),
+        ("(C)", "©"),
+        ("(r)", "®"),
+        ("(R)", "®"),
+        ("(tm)", "™"),
+        ("(TM)", "™"),
+        ("+-", "±"),
+        ("->", "→"),
+        ("=>", "⇒"),
+        ("1/2", "½"),
+        ("1/4", "¼"),
+        ("3/4", "¾"),
+        ("...", "…"),
+    ].iter()
+        .map(|&(original, replacement)| (original.to_string(), replacement.to_string()))
+        .collect()
+}
+
+/// Whether a replacement with the given original text can be made.  Replacements are made in
+/// runs of text and in place of smart punctuation, so the original can't be empty or start
+/// with a character that may begin other syntax, such as a code span, link or raw HTML.
+pub fn is_replaceable(original: &[u8]) -> bool {
+    match original.first() {
+        None => false,
+        Some(&c) => !b"\r\n`\\&<[]!*_'\"".contains(&c),
+    }
+}
+
+/// The smart punctuation used when the `smart` option is set.
+///
+/// The default converts quotes, dashes and ellipses, with English quotation marks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmartPunctuation {
+    /// The quotation marks straight quotes are converted to.  Apostrophes are always converted
+    /// to `’`.
+    pub quotes: QuoteStyle,
+
+    /// Whether `--` and `---` are converted to en and em dashes.
+    pub dashes: bool,
+
+    /// Whether `...` is converted to an ellipsis.
+    pub ellipses: bool,
+}
+
+impl Default for SmartPunctuation {
+    fn default() -> SmartPunctuation {
+        SmartPunctuation {
+            quotes: QuoteStyle::English,
+            dashes: true,
+            ellipses: true,
+        }
+    }
+}
+
+/// The quotation marks used for smart punctuation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteStyle {
+    /// “Double” and ‘single’ quotes.
+    English,
+
+    /// „Double“ and ‚single‘ quotes.
+    German,
+
+    /// «Double» and ‹single› guillemets, with narrow no-break spaces inside them.
+    French,
+
+    /// 「Double」 and 『single』 corner brackets.
+    Japanese,
+}
+
+impl QuoteStyle {
+    /// The opening and closing marks for double quotes.
+    pub fn double(self) -> (&'static str, &'static str) {
+        match self {
+            QuoteStyle::English => ("“", "”"),
+            QuoteStyle::German => ("„", "“"),
+            QuoteStyle::French => ("«\u{202F}", "\u{202F}»"),
+            QuoteStyle::Japanese => ("「", "」"),
+        }
+    }
+
+    /// The opening and closing marks for single quotes.
+    pub fn single(self) -> (&'static str, &'static str) {
+        match self {
+            QuoteStyle::English => ("‘", "’"),
+            QuoteStyle::German => ("‚", "‘"),
+            QuoteStyle::French => ("‹\u{202F}", "\u{202F}›"),
+            QuoteStyle::Japanese => ("『", "』"),
+        }
+    }
+}
+
+impl Default for QuoteStyle {
+    fn default() -> QuoteStyle {
+        QuoteStyle::English
+    }
+}
+
+/// The bullet character used for bullet lists when outputting CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ListStyleType {
+    /// A hyphen, `-`.
+    Dash,
+
+    /// A plus sign, `+`.
+    Plus,
+
+    /// An asterisk, `*`.
+    Star,
+}
+
+impl Default for ListStyleType {
+    fn default() -> ListStyleType {
+        ListStyleType::Dash
+    }
+}
+
+/// How ordered list items are numbered when outputting CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderedListNumbering {
+    /// Items are numbered sequentially from the list's start number.
+    Sequential,
+
+    /// Every item repeats the list's start number, e.g. `1.` for each item.
+    Constant,
+}
+
+impl Default for OrderedListNumbering {
+    fn default() -> OrderedListNumbering {
+        OrderedListNumbering::Sequential
+    }
+}
+
+/// The delimiter character used for emphasis or strong emphasis when outputting CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmphasisDelimiter {
+    /// An asterisk, `*`.
+    Asterisk,
+
+    /// An underscore, `_`.
+    Underscore,
+}
+
+impl Default for EmphasisDelimiter {
+    fn default() -> EmphasisDelimiter {
+        EmphasisDelimiter::Asterisk
+    }
+}
+
+/// The style of level 1 and 2 headings when outputting CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadingStyle {
+    /// [ATX headings](https://github.github.com/gfm/#atx-headings), e.g. `# Heading`.
+    Atx,
+
+    /// [Setext headings](https://github.github.com/gfm/#setext-headings), underlined with `=`
+    /// or `-`.
+    Setext,
+}
+
+impl Default for HeadingStyle {
+    fn default() -> HeadingStyle {
+        HeadingStyle::Atx
+    }
+}
+
+/// The fence character used for fenced code blocks when outputting CommonMark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CodeFenceChar {
+    /// A backtick, `` ` ``.
+    Backtick,
+
+    /// A tilde, `~`.
+    Tilde,
+}
+
+impl Default for CodeFenceChar {
+    fn default() -> CodeFenceChar {
+        CodeFenceChar::Backtick
+    }
 }
 
-#[derive(Clone)]
+/// The kinds of reference detected by the issue, mention and commit linking extensions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferenceKind {
+    /// An issue reference, such as `#123`.
+    Issue,
+
+    /// A user mention, such as `@octocat`.
+    Mention,
+
+    /// A commit SHA, such as `3f2a9c1`.
+    Commit,
+}
+
+#[derive(Debug, Clone)]
 pub struct Reference {
     pub url: Vec<u8>,
     pub title: Vec<u8>,
@@ -347,6 +1459,7 @@ impl<'a, 'o> Parser<'a, 'o> {
         Parser {
             arena: arena,
             refmap: HashMap::new(),
+            abbreviations: HashMap::new(),
             root: root,
             current: root,
             line_number: 0,
@@ -358,6 +1471,7 @@ impl<'a, 'o> Parser<'a, 'o> {
             blank: false,
             partially_consumed_tab: false,
             last_line_length: 0,
+            end_of_input: false,
             options: options,
         }
     }
@@ -478,6 +1592,10 @@ impl<'a, 'o> Parser<'a, 'o> {
             }
         }
 
+        if self.options.ext_spoiler {
+            self.close_spoiler_block(line);
+        }
+
         self.last_line_length = line.len();
         if self.last_line_length > 0 && line[self.last_line_length - 1] == b'\n' {
             self.last_line_length -= 1;
@@ -487,6 +1605,49 @@ impl<'a, 'o> Parser<'a, 'o> {
         }
     }
 
+    /// Closes the innermost open spoiler block if `line`, whose text was just added to the
+    /// current block, ends with `!<`, along with the blocks open inside it.  The `!<` is removed
+    /// from the current block's content.  It is taken literally in code and HTML blocks.
+    fn close_spoiler_block(&mut self, line: &[u8]) {
+        let mut line = line.to_vec();
+        strings::rtrim(&mut line);
+        if !line.ends_with(b"!<") {
+            return;
+        }
+
+        let spoiler = match self
+            .current
+            .ancestors()
+            .find(|n| node_matches!(n, NodeValue::SpoilerBlock))
+        {
+            Some(spoiler) => spoiler,
+            None => return,
+        };
+        match self.current.data.borrow().value {
+            NodeValue::CodeBlock(..) | NodeValue::HtmlBlock(..) => return,
+            _ => (),
+        }
+
+        {
+            let content = &mut self.current.data.borrow_mut().content;
+            strings::rtrim(content);
+            if content.ends_with(b"!<") {
+                let len = content.len() - 2;
+                content.truncate(len);
+                content.push(b'\n');
+            }
+        }
+
+        loop {
+            let node = self.current;
+            self.current = self.finalize(node).unwrap();
+            node.data.borrow_mut().end_line = self.line_number;
+            if node.same_node(spoiler) {
+                break;
+            }
+        }
+    }
+
     fn check_open_blocks(
         &mut self,
         line: &[u8],
@@ -495,6 +1656,15 @@ impl<'a, 'o> Parser<'a, 'o> {
         let (new_all_matched, mut container, should_continue) =
             self.check_open_blocks_inner(self.root, line);
 
+        // A container's closing fence closes it along with any blocks still open inside it.
+        if !should_continue && node_matches!(container, NodeValue::Container(..)) {
+            while !self.current.same_node(container) {
+                self.current = self.finalize(self.current).unwrap();
+            }
+            self.current = self.finalize(container).unwrap();
+            container.data.borrow_mut().end_line = self.line_number;
+        }
+
         *all_matched = new_all_matched;
         if !*all_matched {
             container = container.parent().unwrap();
@@ -524,6 +1694,14 @@ impl<'a, 'o> Parser<'a, 'o> {
                 NodeValue::BlockQuote => if !self.parse_block_quote_prefix(line) {
                     return (false, container, should_continue);
                 },
+                NodeValue::SpoilerBlock => if !self.parse_spoiler_block_prefix(line) {
+                    return (false, container, should_continue);
+                },
+                NodeValue::Container(ref nc) => {
+                    if !self.parse_container_prefix(line, container, nc) {
+                        return (false, container, false);
+                    }
+                }
                 NodeValue::Item(ref nl) => if !self.parse_node_item_prefix(line, container, nl) {
                     return (false, container, should_continue);
                 },
@@ -568,6 +1746,7 @@ impl<'a, 'o> Parser<'a, 'o> {
         let mut matched: usize = 0;
         let mut nl: NodeList = NodeList::default();
         let mut sc: scanners::SetextChar = scanners::SetextChar::Equals;
+        let mut nc = NodeContainer::default();
         let mut maybe_lazy = match self.current.data.borrow().value {
             NodeValue::Paragraph => true,
             _ => false,
@@ -580,7 +1759,16 @@ impl<'a, 'o> Parser<'a, 'o> {
             self.find_first_nonspace(line);
             let indented = self.indent >= CODE_INDENT;
 
-            if !indented && line[self.first_nonspace] == b'>' {
+            if !indented && self.options.ext_spoiler
+                && is_spoiler_marker(&line[self.first_nonspace..])
+            {
+                let offset = self.first_nonspace + 2 - self.offset;
+                self.advance_offset(line, offset, false);
+                if strings::is_space_or_tab(line[self.offset]) {
+                    self.advance_offset(line, 1, true);
+                }
+                *container = self.add_child(*container, NodeValue::SpoilerBlock);
+            } else if !indented && line[self.first_nonspace] == b'>' {
                 let offset = self.first_nonspace + 1 - self.offset;
                 self.advance_offset(line, offset, false);
                 if strings::is_space_or_tab(line[self.offset]) {
@@ -611,6 +1799,17 @@ impl<'a, 'o> Parser<'a, 'o> {
                     level: level,
                     setext: false,
                 });
+            } else if !indented && self.options.ext_container
+                && line[self.first_nonspace] == b':'
+                && unwrap_into(self.parse_container_fence(line), &mut nc)
+            {
+                *container = self.add_child(*container, NodeValue::Container(nc.clone()));
+                let mut end = line.len();
+                while end > self.offset && strings::is_line_end_char(line[end - 1]) {
+                    end -= 1;
+                }
+                let offset = end - self.offset;
+                self.advance_offset(line, offset, false);
             } else if !indented
                 && unwrap_into(
                     scanners::open_code_fence(&line[self.first_nonspace..]),
@@ -680,7 +1879,13 @@ impl<'a, 'o> Parser<'a, 'o> {
                 c = c.split(|&e| e == b']').next().unwrap();
                 let offset = self.first_nonspace + matched - self.offset;
                 self.advance_offset(line, offset, false);
-                *container = self.add_child(*container, NodeValue::FootnoteDefinition(c.to_vec()));
+                *container = self.add_child(
+                    *container,
+                    NodeValue::FootnoteDefinition(NodeFootnoteDefinition {
+                        name: c.to_vec(),
+                        total_references: 0,
+                    }),
+                );
             } else if !indented
                 && self.options.ext_description_lists
                 && line[self.first_nonspace] == b':'
@@ -821,6 +2026,105 @@ impl<'a, 'o> Parser<'a, 'o> {
         false
     }
 
+    fn parse_spoiler_block_prefix(&mut self, line: &[u8]) -> bool {
+        let indent = self.indent;
+        if indent <= 3 && is_spoiler_marker(&line[self.first_nonspace..]) {
+            self.advance_offset(line, indent + 2, true);
+
+            if strings::is_space_or_tab(line[self.offset]) {
+                self.advance_offset(line, 1, true);
+            }
+
+            return true;
+        }
+
+        false
+    }
+
+    fn parse_container_prefix(
+        &mut self,
+        line: &[u8],
+        container: &'a AstNode<'a>,
+        nc: &NodeContainer,
+    ) -> bool {
+        if self.indent > 3 {
+            return true;
+        }
+        let matched = match container_closing_fence(&line[self.first_nonspace..]) {
+            Some(matched) if matched >= nc.fence_length => matched,
+            _ => return true,
+        };
+
+        // The fence belongs to a nested container it's long enough for, or is the content of a
+        // fenced code block, if either is open inside this container.
+        let mut inner = container;
+        while nodes::last_child_is_open(inner) {
+            inner = inner.last_child().unwrap();
+            match inner.data.borrow().value {
+                NodeValue::Container(ref inner_nc) if matched >= inner_nc.fence_length => {
+                    return true
+                }
+                NodeValue::CodeBlock(ref ncb) if ncb.fenced => return true,
+                _ => (),
+            }
+        }
+
+        false
+    }
+
+    /// Parses a container's opening fence at the first non-space character of `line`: at least
+    /// three colons, then a name, attributes in braces or both, then an optional title.
+    fn parse_container_fence(&self, line: &[u8]) -> Option<NodeContainer> {
+        let mut line = &line[self.first_nonspace..];
+        let fence_length = line.iter().take_while(|&&c| c == b':').count();
+        if fence_length < 3 {
+            return None;
+        }
+        line = &line[fence_length..];
+
+        // Pandoc allows the opening fence to be followed by colons too, as in `::: name :::`.
+        let mut end = line.len();
+        while end > 0 && (isspace(line[end - 1]) || line[end - 1] == b':') {
+            end -= 1;
+        }
+        line = &line[..end];
+        while !line.is_empty() && isspace(line[0]) {
+            line = &line[1..];
+        }
+
+        let name_len = line
+            .iter()
+            .take_while(|&&c| !isspace(c) && c != b'{')
+            .count();
+        let name = line[..name_len].to_vec();
+        line = &line[name_len..];
+        while !line.is_empty() && isspace(line[0]) {
+            line = &line[1..];
+        }
+
+        let mut attributes = vec![];
+        if line.first() == Some(&b'{') {
+            if let Some(close) = line.iter().position(|&c| c == b'}') {
+                attributes = parse_container_attributes(&line[1..close]);
+                line = &line[close + 1..];
+                while !line.is_empty() && isspace(line[0]) {
+                    line = &line[1..];
+                }
+            }
+        }
+
+        if name.is_empty() && attributes.is_empty() {
+            return None;
+        }
+
+        Some(NodeContainer {
+            fence_length: fence_length,
+            name: name,
+            attributes: attributes,
+            title: line.to_vec(),
+        })
+    }
+
     fn parse_footnote_definition_block_prefix(&mut self, line: &[u8]) -> bool {
         if self.indent >= 4 {
             self.advance_offset(line, 4, true);
@@ -957,6 +2261,19 @@ impl<'a, 'o> Parser<'a, 'o> {
             };
 
             let item = self.add_child(list, NodeValue::DescriptionItem(metadata));
+            let (term_start_line, term_start_column) = {
+                let ast = last_child.data.borrow();
+                (ast.start_line, ast.start_column)
+            };
+            let mut starts = vec![item];
+            if list.first_child().unwrap().same_node(item) {
+                starts.push(list);
+            }
+            for node in starts {
+                let mut ast = node.data.borrow_mut();
+                ast.start_line = term_start_line;
+                ast.start_column = term_start_column;
+            }
             let term = self.add_child(item, NodeValue::DescriptionTerm);
             let details = self.add_child(item, NodeValue::DescriptionDetails);
 
@@ -975,7 +2292,7 @@ impl<'a, 'o> Parser<'a, 'o> {
             parent = self.finalize(parent).unwrap();
         }
 
-        let child = make_block(value, self.line_number);
+        let child = make_block(value, self.line_number, self.first_nonspace + 1);
         let node = self.arena.alloc(Node::new(RefCell::new(child)));
         parent.append(node);
         node
@@ -997,7 +2314,11 @@ impl<'a, 'o> Parser<'a, 'o> {
 
         container.data.borrow_mut().last_line_blank = self.blank
             && match container.data.borrow().value {
-                NodeValue::BlockQuote | NodeValue::Heading(..) | NodeValue::ThematicBreak => false,
+                NodeValue::BlockQuote
+                | NodeValue::SpoilerBlock
+                | NodeValue::Container(..)
+                | NodeValue::Heading(..)
+                | NodeValue::ThematicBreak => false,
                 NodeValue::CodeBlock(ref ncb) => !ncb.fenced,
                 NodeValue::Item(..) => {
                     container.first_child().is_some()
@@ -1047,7 +2368,9 @@ impl<'a, 'o> Parser<'a, 'o> {
                     };
 
                     if matches_end_condition {
+                        let html_block = container;
                         container = self.finalize(container).unwrap();
+                        html_block.data.borrow_mut().end_line = self.line_number;
                     }
                 }
                 _ => {
@@ -1115,6 +2438,7 @@ impl<'a, 'o> Parser<'a, 'o> {
     }
 
     fn finalize_document(&mut self) {
+        self.end_of_input = true;
         while !self.current.same_node(self.root) {
             self.current = self.finalize(self.current).unwrap();
         }
@@ -1138,6 +2462,12 @@ impl<'a, 'o> Parser<'a, 'o> {
         assert!(ast.open);
         ast.open = false;
 
+        ast.end_line = match ast.value {
+            _ if self.end_of_input => self.line_number,
+            NodeValue::CodeBlock(ref ncb) if ncb.fenced => self.line_number,
+            _ => self.line_number - 1,
+        };
+
         let content = &mut ast.content;
         let mut pos = 0;
 
@@ -1149,8 +2479,11 @@ impl<'a, 'o> Parser<'a, 'o> {
                 {
                     let mut seek: &[u8] = &*content;
                     while !seek.is_empty()
-                        && seek[0] == b'['
-                        && unwrap_into(self.parse_reference_inline(seek), &mut pos)
+                        && (seek[0] == b'['
+                            && unwrap_into(self.parse_reference_inline(seek), &mut pos)
+                            || self.options.ext_abbreviations
+                                && seek.starts_with(b"*[")
+                                && unwrap_into(self.parse_abbreviation(seek), &mut pos))
                     {
                         seek = &seek[pos..];
                         seeked += pos;
@@ -1271,82 +2604,164 @@ impl<'a, 'o> Parser<'a, 'o> {
 
     fn process_footnotes(&mut self) {
         let mut map = HashMap::new();
-        Self::find_footnote_definitions(self.root, &mut map);
+        let mut unreferenced = vec![];
+        Self::find_footnote_definitions(self.root, &mut map, &mut unreferenced);
+
+        // Labels referenced in the document, which generated labels must avoid even if they
+        // are undefined.
+        let referenced = self
+            .root
+            .descendants()
+            .filter_map(|n| match n.data.borrow().value {
+                NodeValue::FootnoteReference(ref nfr) if n.first_child().is_none() => {
+                    Some(strings::normalize_label(&nfr.name))
+                }
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
 
         let mut ix = 0;
-        Self::find_footnote_references(self.root, &mut map, &mut ix);
-
-        if ix > 0 {
-            let mut v = map.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
-            v.sort_unstable_by(|a, b| a.ix.cmp(&b.ix));
-            for f in v {
-                if f.ix.is_some() {
-                    self.root.append(f.node);
+        let mut inline_ix = 0;
+        self.find_footnote_references(self.root, &mut map, &referenced, &mut ix, &mut inline_ix);
+
+        let mut v = map.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+        v.sort_unstable_by(|a, b| a.ix.cmp(&b.ix));
+        for f in v {
+            if f.ix.is_some() {
+                self.root.append(f.node);
+            }
+        }
+        for node in unreferenced {
+            if let NodeValue::FootnoteDefinition(ref nfd) = node.data.borrow().value {
+                if nfd.total_references > 0 {
+                    continue;
                 }
             }
+            self.root.append(node);
         }
     }
 
     fn find_footnote_definitions(
         node: &'a AstNode<'a>,
         map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
+        all: &mut Vec<&'a AstNode<'a>>,
     ) {
         match node.data.borrow().value {
-            NodeValue::FootnoteDefinition(ref name) => {
+            NodeValue::FootnoteDefinition(ref nfd) => {
                 node.detach();
-                map.insert(
-                    strings::normalize_label(name),
-                    FootnoteDefinition {
+                all.push(node);
+                map.entry(strings::normalize_label(&nfd.name))
+                    .or_insert(FootnoteDefinition {
                         ix: None,
                         node: node,
-                    },
-                );
+                    });
             }
             _ => for n in node.children() {
-                Self::find_footnote_definitions(n, map);
+                Self::find_footnote_definitions(n, map, all);
             },
         }
     }
 
     fn find_footnote_references(
+        &mut self,
         node: &'a AstNode<'a>,
-        map: &mut HashMap<Vec<u8>, FootnoteDefinition>,
+        map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
+        referenced: &HashSet<Vec<u8>>,
         ix: &mut u32,
+        inline_ix: &mut u32,
     ) {
+        if !node_matches!(node, NodeValue::FootnoteReference(..)) {
+            for n in node.children() {
+                self.find_footnote_references(n, map, referenced, ix, inline_ix);
+            }
+            return;
+        }
+
+        if node.first_child().is_some() {
+            self.make_inline_footnote(node, map, referenced, inline_ix);
+        }
+
         let mut ast = node.data.borrow_mut();
-        let mut replace = None;
-        match ast.value {
-            NodeValue::FootnoteReference(ref mut name) => {
-                if let Some(ref mut footnote) = map.get_mut(name) {
-                    if footnote.ix.is_none() {
-                        *ix += 1;
-                        footnote.ix = Some(*ix);
-                    }
+        let nfr = match ast.value {
+            NodeValue::FootnoteReference(ref mut nfr) => nfr,
+            _ => unreachable!(),
+        };
+        if let Some(ref mut footnote) = map.get_mut(&strings::normalize_label(&nfr.name)) {
+            if footnote.ix.is_none() {
+                *ix += 1;
+                footnote.ix = Some(*ix);
+            }
+            nfr.ix = footnote.ix.unwrap();
 
-                    *name = format!("{}", footnote.ix.unwrap()).into_bytes();
-                } else {
-                    replace = Some(name.clone());
-                }
+            if let NodeValue::FootnoteDefinition(ref mut nfd) =
+                footnote.node.data.borrow_mut().value
+            {
+                nfd.total_references += 1;
+                nfr.ref_num = nfd.total_references;
             }
-            _ => for n in node.children() {
-                Self::find_footnote_references(n, map, ix);
-            },
+        }
+    }
+
+    /// Moves the content of an inline footnote, held as the children of its reference, into a
+    /// new definition with a generated label that no other footnote uses.
+    fn make_inline_footnote(
+        &mut self,
+        reference: &'a AstNode<'a>,
+        map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
+        referenced: &HashSet<Vec<u8>>,
+        inline_ix: &mut u32,
+    ) {
+        let name = loop {
+            *inline_ix += 1;
+            let name = format!("inline-{}", inline_ix).into_bytes();
+            if !map.contains_key(&name) && !referenced.contains(&name) {
+                break name;
+            }
+        };
+
+        let definition = nodes::new_node(
+            self.arena,
+            NodeValue::FootnoteDefinition(NodeFootnoteDefinition {
+                name: name.clone(),
+                total_references: 0,
+            }),
+        );
+        let paragraph = nodes::new_node(self.arena, NodeValue::Paragraph);
+        definition.append(paragraph);
+        while let Some(child) = reference.first_child() {
+            paragraph.append(child);
         }
 
-        if let Some(mut label) = replace {
-            label.insert(0, b'[');
-            label.insert(1, b'^');
-            let len = label.len();
-            label.insert(len, b']');
-            ast.value = NodeValue::Text(label);
+        let line = reference
+            .ancestors()
+            .skip(1)
+            .map(|n| n.data.borrow().start_line)
+            .find(|&line| line > 0)
+            .unwrap_or(0);
+        for node in &[definition, paragraph] {
+            let mut ast = node.data.borrow_mut();
+            ast.start_line = line;
+            ast.end_line = line;
         }
+
+        if let NodeValue::FootnoteReference(ref mut nfr) = reference.data.borrow_mut().value {
+            nfr.name = name.clone();
+        }
+        map.insert(
+            name,
+            FootnoteDefinition {
+                ix: None,
+                node: definition,
+            },
+        );
     }
 
     fn postprocess_text_nodes(&mut self, node: &'a AstNode<'a>) {
-        let mut stack = vec![node];
+        // Whether each node is within a link is carried alongside it.
+        let mut stack = vec![(node, false)];
         let mut children = vec![];
 
-        while let Some(node) = stack.pop() {
+        while let Some((node, in_link)) = stack.pop() {
             let mut nch = node.first_child();
 
             while let Some(n) = nch {
@@ -1357,7 +2772,7 @@ impl<'a, 'o> Parser<'a, 'o> {
                             let ns = match n.next_sibling() {
                                 Some(ns) => ns,
                                 _ => {
-                                    self.postprocess_text_node(n, root);
+                                    self.postprocess_text_node(n, root, in_link);
                                     break;
                                 }
                             };
@@ -1368,12 +2783,20 @@ impl<'a, 'o> Parser<'a, 'o> {
                                     ns.detach();
                                 }
                                 _ => {
-                                    self.postprocess_text_node(n, root);
+                                    self.postprocess_text_node(n, root, in_link);
                                     break;
                                 }
                             }
                         }
-                        NodeValue::Link(..) | NodeValue::Image(..) => {
+                        // Link text may contain abbreviations, but nothing else found in text.
+                        NodeValue::Link(..) if !self.abbreviations.is_empty() => {
+                            children.push((n, true));
+                            this_bracket = true;
+                            break;
+                        }
+                        NodeValue::Link(..)
+                        | NodeValue::Image(..)
+                        | NodeValue::Abbreviation(..) => {
                             this_bracket = true;
                             break;
                         }
@@ -1382,7 +2805,7 @@ impl<'a, 'o> Parser<'a, 'o> {
                 }
 
                 if !this_bracket {
-                    children.push(n);
+                    children.push((n, in_link));
                 }
 
                 nch = n.next_sibling();
@@ -1394,13 +2817,31 @@ impl<'a, 'o> Parser<'a, 'o> {
         }
     }
 
-    fn postprocess_text_node(&mut self, node: &'a AstNode<'a>, text: &mut Vec<u8>) {
-        if self.options.ext_tasklist {
+    fn postprocess_text_node(
+        &mut self,
+        node: &'a AstNode<'a>,
+        text: &mut Vec<u8>,
+        in_link: bool,
+    ) {
+        if !in_link && self.options.ext_tasklist {
             self.process_tasklist(node, text);
         }
 
-        if self.options.ext_autolink {
-            autolink::process_autolinks(self.arena, node, text);
+        if !in_link && self.options.ext_autolink {
+            autolink::process_autolinks(self.arena, node, text, self.options);
+        }
+
+        if !in_link
+            && (self.options.ext_issue_url.is_some()
+                || self.options.ext_mention_url.is_some()
+                || self.options.ext_commit_url.is_some()
+                || self.options.ext_reference_resolver.is_some())
+        {
+            references::process_references(self.arena, node, text, self.options);
+        }
+
+        if !self.abbreviations.is_empty() {
+            abbreviations::process_abbreviations(self.arena, node, text, &self.abbreviations);
         }
     }
 
@@ -1409,12 +2850,9 @@ impl<'a, 'o> Parser<'a, 'o> {
             static ref TASKLIST: Regex = Regex::new(r"\A(\s*\[([xX ])\])(?:\z|\s)").unwrap();
         }
 
-        let (active, end) = match TASKLIST.captures(text) {
+        let (symbol, end) = match TASKLIST.captures(text) {
             None => return,
-            Some(c) => (
-                c.get(2).unwrap().as_bytes() != b" ",
-                c.get(1).unwrap().end(),
-            ),
+            Some(c) => (c.get(2).unwrap().as_bytes()[0], c.get(1).unwrap().end()),
         };
 
         let parent = node.parent().unwrap();
@@ -1435,15 +2873,47 @@ impl<'a, 'o> Parser<'a, 'o> {
         *text = text[end..].to_vec();
         let checkbox = inlines::make_inline(
             self.arena,
-            NodeValue::HtmlInline(if active {
-                b"<input type=\"checkbox\" disabled=\"\" checked=\"\" />".to_vec()
-            } else {
-                b"<input type=\"checkbox\" disabled=\"\" />".to_vec()
+            NodeValue::TaskItem(NodeTaskItem {
+                checked: symbol != b' ',
+                symbol: symbol,
             }),
         );
         node.insert_before(checkbox);
     }
 
+    /// Parses an abbreviation definition, `*[text]: title`, at the start of `content`, returning
+    /// its length including the line ending.
+    fn parse_abbreviation(&mut self, content: &[u8]) -> Option<usize> {
+        let line_len = content
+            .iter()
+            .position(|&c| strings::is_line_end_char(c))
+            .unwrap_or(content.len());
+        let line = &content[..line_len];
+
+        let close = match line.iter().position(|&c| c == b']') {
+            Some(close) if line.get(close + 1) == Some(&b':') => close,
+            _ => return None,
+        };
+        let mut text = line[2..close].to_vec();
+        strings::trim(&mut text);
+        if text.is_empty() {
+            return None;
+        }
+        let mut title = line[close + 2..].to_vec();
+        strings::trim(&mut title);
+
+        self.abbreviations.entry(text).or_insert(title);
+
+        let mut pos = line_len;
+        if content.get(pos) == Some(&b'\r') {
+            pos += 1;
+        }
+        if content.get(pos) == Some(&b'\n') {
+            pos += 1;
+        }
+        Some(pos)
+    }
+
     fn parse_reference_inline(&mut self, content: &[u8]) -> Option<usize> {
         // In this case reference inlines rarely have delimiters
         // so we often just need the minimal case
@@ -1618,6 +3088,67 @@ fn parse_list_marker(
     None
 }
 
+/// Whether `line` starts with a spoiler block marker, `>!`, rather than a block quote marker
+/// followed by an image, as in `>![alt](src)`.
+fn is_spoiler_marker(line: &[u8]) -> bool {
+    line.starts_with(b">!") && line.get(2) != Some(&b'[')
+}
+
+/// Returns the length of the container closing fence `line` starts with: at least three colons,
+/// followed only by whitespace.
+fn container_closing_fence(line: &[u8]) -> Option<usize> {
+    let len = line.iter().take_while(|&&c| c == b':').count();
+    if len >= 3 && line[len..].iter().all(|&c| isspace(c)) {
+        Some(len)
+    } else {
+        None
+    }
+}
+
+/// Parses the attributes between the braces of a container's opening fence, such as
+/// `#id .class key=value title="A title"`.
+fn parse_container_attributes(mut s: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut attributes = vec![];
+    loop {
+        while !s.is_empty() && isspace(s[0]) {
+            s = &s[1..];
+        }
+        if s.is_empty() {
+            return attributes;
+        }
+
+        let word_len = s.iter().take_while(|&&c| !isspace(c)).count();
+        match s[0] {
+            b'#' => attributes.push((b"id".to_vec(), s[1..word_len].to_vec())),
+            b'.' => attributes.push((b"class".to_vec(), s[1..word_len].to_vec())),
+            _ => {
+                let key_len = s.iter().take_while(|&&c| !isspace(c) && c != b'=').count();
+                let key = s[..key_len].to_vec();
+                s = &s[key_len..];
+                if s.first() != Some(&b'=') {
+                    attributes.push((key, vec![]));
+                    continue;
+                }
+                s = &s[1..];
+
+                let value_len = if s.first() == Some(&b'"') {
+                    s = &s[1..];
+                    let len = s.iter().position(|&c| c == b'"').unwrap_or(s.len());
+                    attributes.push((key, s[..len].to_vec()));
+                    min(len + 1, s.len())
+                } else {
+                    let len = s.iter().take_while(|&&c| !isspace(c)).count();
+                    attributes.push((key, s[..len].to_vec()));
+                    len
+                };
+                s = &s[value_len..];
+                continue;
+            }
+        }
+        s = &s[word_len..];
+    }
+}
+
 pub fn unwrap_into<T>(t: Option<T>, out: &mut T) -> bool {
     match t {
         Some(v) => {
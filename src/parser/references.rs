@@ -0,0 +1,111 @@
+use ctype::{isalnum, isdigit};
+use nodes::{AstNode, NodeLink, NodeValue};
+use parser::inlines::make_inline;
+use parser::{ComrakOptions, ReferenceKind};
+use std::str;
+use typed_arena::Arena;
+
+pub fn process_references<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    node: &'a AstNode<'a>,
+    contents: &mut Vec<u8>,
+    options: &ComrakOptions,
+) {
+    let len = contents.len();
+    let mut i = 0;
+
+    while i < len {
+        if i == 0 || !is_word_byte(contents[i - 1]) {
+            if let Some((inl, skip)) = reference_match(arena, &contents[i..], options) {
+                node.insert_after(inl);
+                if i + skip < len {
+                    let remain = contents[i + skip..].to_vec();
+                    inl.insert_after(make_inline(arena, NodeValue::Text(remain)));
+                }
+                contents.truncate(i);
+                return;
+            }
+        }
+        i += 1;
+    }
+}
+
+fn is_word_byte(c: u8) -> bool {
+    isalnum(c) || c == b'_' || c == b'-' || c == b'/' || c == b'@' || c == b'#' || c == b'&'
+}
+
+fn is_hex_byte(c: u8) -> bool {
+    isdigit(c) || (c >= b'a' && c <= b'f')
+}
+
+fn reference_match<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    data: &[u8],
+    options: &ComrakOptions,
+) -> Option<(&'a AstNode<'a>, usize)> {
+    let (kind, id_start, end) = match data[0] {
+        b'#' => {
+            let digits = data[1..].iter().take_while(|&&c| isdigit(c)).count();
+            if digits == 0 {
+                return None;
+            }
+            (ReferenceKind::Issue, 1, 1 + digits)
+        }
+        b'@' => {
+            let mut name = data[1..]
+                .iter()
+                .take(39)
+                .take_while(|&&c| isalnum(c) || c == b'-')
+                .count();
+            while name > 0 && data[name] == b'-' {
+                name -= 1;
+            }
+            if name == 0 || data[1] == b'-' {
+                return None;
+            }
+            (ReferenceKind::Mention, 1, 1 + name)
+        }
+        c if is_hex_byte(c) => {
+            let hex = data.iter().take_while(|&&c| is_hex_byte(c)).count();
+            let digits = data[..hex].iter().filter(|&&c| isdigit(c)).count();
+            if hex < 7 || hex > 40 || digits == 0 || digits == hex {
+                return None;
+            }
+            (ReferenceKind::Commit, 0, hex)
+        }
+        _ => return None,
+    };
+
+    if data.get(end).map_or(false, |&c| isalnum(c) || c == b'_') {
+        return None;
+    }
+
+    let id = str::from_utf8(&data[id_start..end]).unwrap();
+    let url = match resolve(kind, id, options) {
+        Some(url) => url,
+        None => return None,
+    };
+
+    let inl = make_inline(
+        arena,
+        NodeValue::Link(NodeLink {
+            url: url.into_bytes(),
+            title: vec![],
+        }),
+    );
+    inl.append(make_inline(arena, NodeValue::Text(data[..end].to_vec())));
+    Some((inl, end))
+}
+
+fn resolve(kind: ReferenceKind, id: &str, options: &ComrakOptions) -> Option<String> {
+    if let Some(ref resolver) = options.ext_reference_resolver {
+        return resolver(kind, id);
+    }
+
+    let template = match kind {
+        ReferenceKind::Issue => &options.ext_issue_url,
+        ReferenceKind::Mention => &options.ext_mention_url,
+        ReferenceKind::Commit => &options.ext_commit_url,
+    };
+    template.as_ref().map(|t| t.replace("{}", id))
+}
@@ -58,11 +58,17 @@ fn try_opening_header<'a, 'o>(
         });
     }
 
-    let child = make_block(NodeValue::Table(alignments), parser.line_number);
+    let (start_line, start_column) = {
+        let ast = container.data.borrow();
+        (ast.start_line, ast.start_column)
+    };
+    let child = make_block(NodeValue::Table(alignments), start_line, start_column);
     let table = parser.arena.alloc(Node::new(RefCell::new(child)));
     container.append(table);
 
     let header = parser.add_child(table, NodeValue::TableRow(true));
+    header.data.borrow_mut().start_line = start_line;
+    header.data.borrow_mut().start_column = start_column;
     for header_str in header_row {
         let header_cell = parser.add_child(header, NodeValue::TableCell);
         header_cell.data.borrow_mut().content = header_str;
@@ -0,0 +1,496 @@
+//! Selecting nodes from the AST with CSS-like selectors.
+//!
+//! A selector names node types in snake case (`heading`, `code_block`, `link`, or `*` for any
+//! node), optionally followed by attribute tests in brackets.  Unknown node types are an error.
+//! Selectors may be combined with whitespace (descendant), `>` (child) and `,` (either).
+//!
+//! ```
+//! # use comrak::{parse_document, Arena, ComrakOptions};
+//! # use comrak::query::select;
+//! let arena = Arena::new();
+//! let root = parse_document(
+//!     &arena,
+//!     "## [One](/one)\n\n```rust\nfn main() {}\n```\n\n```python\npass\n```\n",
+//!     &ComrakOptions::default());
+//!
+//! let links = select(root, "heading[level=2] > link").unwrap();
+//! assert_eq!(links.len(), 1);
+//!
+//! let rust = select(root, "code_block[lang=rust]").unwrap();
+//! assert_eq!(rust.len(), 1);
+//! ```
+//!
+//! The attributes available on each node type are:
+//!
+//! * `heading`: `level`, `setext`
+//! * `code_block`: `info`, `lang` (the first word of `info`), `fenced`, `literal`
+//! * `html_block`, `text`, `code`, `html_inline`: `literal`
+//! * `list`, `item`: `type` (`bullet` or `ordered`), `start`, `bullet`, `tight`
+//! * `link`, `image`: `url`, `title`
+//! * `table_row`: `header`
+//! * `task_item`: `checked`
+//! * `footnote_definition`, `footnote_reference`: `name`
+//! * `footnote_reference`: `ix` (its number, or 0 if the footnote is undefined)
+//! * `replacement`: `original`, `replacement`
+//! * `container`: `name`, `title`, and its own attributes, with several classes separated by
+//!   spaces
+//! * `abbreviation`: `title`
+//!
+//! Attribute tests may be `[name]` (the attribute is present), `[name=value]`, `[name^=prefix]`,
+//! `[name$=suffix]` or `[name*=substring]`.  Values may be quoted with `"` or `'`.
+
+use nodes::{AstNode, ListType, NodeValue};
+use std::error::Error;
+use std::fmt;
+use std::str;
+
+/// A parsed selector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    alternatives: Vec<Vec<Step>>,
+}
+
+/// An error in the syntax of a selector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    /// A description of the error.
+    pub message: String,
+
+    /// The byte offset in the selector at which the error was found.
+    pub position: usize,
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for SelectorError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Exists,
+    Equals,
+    Prefix,
+    Suffix,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Test {
+    name: String,
+    operator: Operator,
+    value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    // The combinator relating this step to the previous one.
+    combinator: Combinator,
+    node_type: Option<String>,
+    tests: Vec<Test>,
+}
+
+/// Returns all nodes under and including `root` which match `selector`, in document order.
+pub fn select<'a>(
+    root: &'a AstNode<'a>,
+    selector: &str,
+) -> Result<Vec<&'a AstNode<'a>>, SelectorError> {
+    Ok(Selector::parse(selector)?.select(root))
+}
+
+impl Selector {
+    /// Parses a selector.
+    pub fn parse(selector: &str) -> Result<Selector, SelectorError> {
+        SelectorParser {
+            input: selector.as_bytes(),
+            pos: 0,
+        }.parse()
+    }
+
+    /// Returns all nodes under and including `root` which match, in document order.
+    pub fn select<'a>(&self, root: &'a AstNode<'a>) -> Vec<&'a AstNode<'a>> {
+        root.descendants().filter(|n| self.matches(n)).collect()
+    }
+
+    /// Returns whether `node` matches the selector.
+    pub fn matches<'a>(&self, node: &'a AstNode<'a>) -> bool {
+        self.alternatives
+            .iter()
+            .any(|steps| matches_steps(node, steps))
+    }
+}
+
+fn matches_steps<'a>(node: &'a AstNode<'a>, steps: &[Step]) -> bool {
+    let (last, rest) = match steps.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+
+    if !matches_step(node, last) {
+        return false;
+    }
+    if rest.is_empty() {
+        return true;
+    }
+
+    match last.combinator {
+        Combinator::Child => node.parent().map_or(false, |p| matches_steps(p, rest)),
+        Combinator::Descendant => node.ancestors().skip(1).any(|a| matches_steps(a, rest)),
+    }
+}
+
+fn matches_step<'a>(node: &'a AstNode<'a>, step: &Step) -> bool {
+    let value = &node.data.borrow().value;
+
+    if let Some(ref node_type) = step.node_type {
+        if node_type_name(value) != node_type {
+            return false;
+        }
+    }
+
+    step.tests.iter().all(|test| match attribute(value, &test.name) {
+        None => false,
+        Some(actual) => match test.operator {
+            Operator::Exists => true,
+            Operator::Equals => actual == test.value,
+            Operator::Prefix => actual.starts_with(&test.value),
+            Operator::Suffix => actual.ends_with(&test.value),
+            Operator::Contains => actual.contains(&test.value),
+        },
+    })
+}
+
+/// Every name `node_type_name` returns.
+const NODE_TYPE_NAMES: &[&str] = &[
+    "document",
+    "block_quote",
+    "list",
+    "item",
+    "description_list",
+    "description_item",
+    "description_term",
+    "description_details",
+    "code_block",
+    "html_block",
+    "paragraph",
+    "heading",
+    "thematic_break",
+    "footnote_definition",
+    "table",
+    "table_row",
+    "table_cell",
+    "spoiler_block",
+    "container",
+    "text",
+    "softbreak",
+    "linebreak",
+    "code",
+    "html_inline",
+    "emph",
+    "strong",
+    "strikethrough",
+    "superscript",
+    "subscript",
+    "highlight",
+    "insert",
+    "underline",
+    "spoiler",
+    "link",
+    "image",
+    "footnote_reference",
+    "task_item",
+    "replacement",
+    "abbreviation",
+];
+
+/// The name by which selectors refer to the type of a node.
+pub fn node_type_name(value: &NodeValue) -> &'static str {
+    match *value {
+        NodeValue::Document => "document",
+        NodeValue::BlockQuote => "block_quote",
+        NodeValue::List(..) => "list",
+        NodeValue::Item(..) => "item",
+        NodeValue::DescriptionList => "description_list",
+        NodeValue::DescriptionItem(..) => "description_item",
+        NodeValue::DescriptionTerm => "description_term",
+        NodeValue::DescriptionDetails => "description_details",
+        NodeValue::CodeBlock(..) => "code_block",
+        NodeValue::HtmlBlock(..) => "html_block",
+        NodeValue::Paragraph => "paragraph",
+        NodeValue::Heading(..) => "heading",
+        NodeValue::ThematicBreak => "thematic_break",
+        NodeValue::FootnoteDefinition(..) => "footnote_definition",
+        NodeValue::Table(..) => "table",
+        NodeValue::TableRow(..) => "table_row",
+        NodeValue::TableCell => "table_cell",
+        NodeValue::SpoilerBlock => "spoiler_block",
+        NodeValue::Container(..) => "container",
+        NodeValue::Text(..) => "text",
+        NodeValue::SoftBreak => "softbreak",
+        NodeValue::LineBreak => "linebreak",
+        NodeValue::Code(..) => "code",
+        NodeValue::HtmlInline(..) => "html_inline",
+        NodeValue::Emph => "emph",
+        NodeValue::Strong => "strong",
+        NodeValue::Strikethrough => "strikethrough",
+        NodeValue::Superscript => "superscript",
+        NodeValue::Subscript => "subscript",
+        NodeValue::Highlight => "highlight",
+        NodeValue::Insert => "insert",
+        NodeValue::Underline => "underline",
+        NodeValue::Spoiler => "spoiler",
+        NodeValue::Link(..) => "link",
+        NodeValue::Image(..) => "image",
+        NodeValue::FootnoteReference(..) => "footnote_reference",
+        NodeValue::TaskItem(..) => "task_item",
+        NodeValue::Replacement(..) => "replacement",
+        NodeValue::Abbreviation(..) => "abbreviation",
+    }
+}
+
+fn attribute(value: &NodeValue, name: &str) -> Option<String> {
+    let text = |bytes: &[u8]| Some(String::from_utf8_lossy(bytes).into_owned());
+
+    match *value {
+        NodeValue::Heading(ref nh) if name == "level" => Some(nh.level.to_string()),
+        NodeValue::Heading(ref nh) if name == "setext" => Some(nh.setext.to_string()),
+        NodeValue::CodeBlock(ref ncb) if name == "info" => text(&ncb.info),
+        NodeValue::CodeBlock(ref ncb) if name == "lang" => text(
+            ncb.info
+                .split(|&c| c == b' ' || c == b'\t')
+                .next()
+                .unwrap_or(&[]),
+        ),
+        NodeValue::CodeBlock(ref ncb) if name == "fenced" => Some(ncb.fenced.to_string()),
+        NodeValue::CodeBlock(ref ncb) if name == "literal" => text(&ncb.literal),
+        NodeValue::HtmlBlock(ref nhb) if name == "literal" => text(&nhb.literal),
+        NodeValue::Container(ref nc) if name == "name" => text(&nc.name),
+        NodeValue::Container(ref nc) if name == "title" => text(&nc.title),
+        NodeValue::Container(ref nc) => {
+            let values = nc
+                .attributes
+                .iter()
+                .filter(|attribute| attribute.0 == name.as_bytes())
+                .map(|attribute| &attribute.1[..])
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                None
+            } else {
+                text(&values.join(&b' '))
+            }
+        }
+        NodeValue::Text(ref literal)
+        | NodeValue::Code(ref literal)
+        | NodeValue::HtmlInline(ref literal) if name == "literal" => text(literal),
+        NodeValue::List(ref nl) | NodeValue::Item(ref nl) => match name {
+            "type" => Some(
+                match nl.list_type {
+                    ListType::Bullet => "bullet",
+                    ListType::Ordered => "ordered",
+                }.to_string(),
+            ),
+            "start" if nl.list_type == ListType::Ordered => Some(nl.start.to_string()),
+            "bullet" if nl.list_type == ListType::Bullet => text(&[nl.bullet_char]),
+            "tight" => Some(nl.tight.to_string()),
+            _ => None,
+        },
+        NodeValue::Link(ref nl) | NodeValue::Image(ref nl) if name == "url" => text(&nl.url),
+        NodeValue::Link(ref nl) | NodeValue::Image(ref nl) if name == "title" => text(&nl.title),
+        NodeValue::TableRow(header) if name == "header" => Some(header.to_string()),
+        NodeValue::TaskItem(ref nti) if name == "checked" => Some(nti.checked.to_string()),
+        NodeValue::FootnoteDefinition(ref nfd) if name == "name" => text(&nfd.name),
+        NodeValue::FootnoteReference(ref nfr) if name == "name" => text(&nfr.name),
+        NodeValue::FootnoteReference(ref nfr) if name == "ix" => Some(nfr.ix.to_string()),
+        NodeValue::Replacement(ref nr) if name == "original" => text(&nr.original),
+        NodeValue::Replacement(ref nr) if name == "replacement" => text(&nr.replacement),
+        NodeValue::Abbreviation(ref na) if name == "title" => text(&na.title),
+        _ => None,
+    }
+}
+
+struct SelectorParser<'i> {
+    input: &'i [u8],
+    pos: usize,
+}
+
+impl<'i> SelectorParser<'i> {
+    fn parse(&mut self) -> Result<Selector, SelectorError> {
+        let mut alternatives = vec![];
+        loop {
+            alternatives.push(self.parse_steps()?);
+            if self.peek() == Some(b',') {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(Selector {
+            alternatives: alternatives,
+        })
+    }
+
+    fn parse_steps(&mut self) -> Result<Vec<Step>, SelectorError> {
+        let mut steps = vec![];
+        let mut combinator = Combinator::Descendant;
+        loop {
+            self.skip_whitespace();
+            let mut step = self.parse_step()?;
+            step.combinator = combinator;
+            steps.push(step);
+
+            let had_space = self.skip_whitespace();
+            match self.peek() {
+                None | Some(b',') => return Ok(steps),
+                Some(b'>') => {
+                    self.pos += 1;
+                    combinator = Combinator::Child;
+                }
+                Some(_) if had_space => combinator = Combinator::Descendant,
+                Some(c) => return Err(self.error(format!("unexpected '{}'", c as char))),
+            }
+        }
+    }
+
+    fn parse_step(&mut self) -> Result<Step, SelectorError> {
+        let node_type = if self.peek() == Some(b'*') {
+            self.pos += 1;
+            None
+        } else {
+            let start = self.pos;
+            let name = self.parse_name();
+            if name.is_empty() {
+                return Err(self.error("expected a node type".to_string()));
+            }
+            if !NODE_TYPE_NAMES.contains(&&name[..]) {
+                return Err(SelectorError {
+                    message: format!("unknown node type '{}'", name),
+                    position: start,
+                });
+            }
+            Some(name)
+        };
+
+        let mut tests = vec![];
+        while self.peek() == Some(b'[') {
+            self.pos += 1;
+            tests.push(self.parse_test()?);
+        }
+
+        Ok(Step {
+            combinator: Combinator::Descendant,
+            node_type: node_type,
+            tests: tests,
+        })
+    }
+
+    fn parse_test(&mut self) -> Result<Test, SelectorError> {
+        self.skip_whitespace();
+        let name = self.parse_name();
+        if name.is_empty() {
+            return Err(self.error("expected an attribute name".to_string()));
+        }
+        self.skip_whitespace();
+
+        let operator = match self.peek() {
+            Some(b']') => Operator::Exists,
+            Some(b'=') => Operator::Equals,
+            Some(b'^') => Operator::Prefix,
+            Some(b'$') => Operator::Suffix,
+            Some(b'*') => Operator::Contains,
+            _ => return Err(self.error("expected an attribute operator or ']'".to_string())),
+        };
+
+        let mut value = String::new();
+        if operator != Operator::Exists {
+            self.pos += if operator == Operator::Equals { 1 } else { 2 };
+            if operator != Operator::Equals && self.input.get(self.pos - 1) != Some(&b'=') {
+                return Err(self.error("expected '='".to_string()));
+            }
+            self.skip_whitespace();
+            value = self.parse_value()?;
+            self.skip_whitespace();
+        }
+
+        if self.peek() != Some(b']') {
+            return Err(self.error("expected ']'".to_string()));
+        }
+        self.pos += 1;
+
+        Ok(Test {
+            name: name,
+            operator: operator,
+            value: value,
+        })
+    }
+
+    fn parse_value(&mut self) -> Result<String, SelectorError> {
+        match self.peek() {
+            Some(quote) if quote == b'"' || quote == b'\'' => {
+                let start = self.pos + 1;
+                match self.input[start..].iter().position(|&c| c == quote) {
+                    Some(len) => {
+                        self.pos = start + len + 1;
+                        Ok(self.slice(start, start + len))
+                    }
+                    None => Err(self.error("unterminated string".to_string())),
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while let Some(c) = self.peek() {
+                    if c == b']' || c == b' ' {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                Ok(self.slice(start, self.pos))
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> String {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == b'_' || c == b'-') {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.slice(start, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+        self.pos != start
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn slice(&self, start: usize, end: usize) -> String {
+        str::from_utf8(&self.input[start..end]).unwrap().to_string()
+    }
+
+    fn error(&self, message: String) -> SelectorError {
+        SelectorError {
+            message: message,
+            position: self.pos,
+        }
+    }
+}
@@ -0,0 +1,361 @@
+//! Sanitization of raw HTML against an allowlist.
+
+use ctype::{isalnum, isalpha, isdigit, isspace};
+use entity;
+use std::collections::HashMap;
+
+/// An allowlist of HTML tags, attributes and URL schemes, used to sanitize raw HTML when the
+/// `sanitize` option is set.
+///
+/// Tags not in the allowlist are removed, but their content is kept, except for the content of
+/// `script`, `style` and similar elements within an HTML block, which is removed too.  Comments,
+/// processing instructions and declarations are always removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SanitizePolicy {
+    /// The allowed tag names, in lower case, each with the attributes allowed on that tag.
+    pub tags: HashMap<String, Vec<String>>,
+
+    /// Attributes allowed on every allowed tag.
+    pub generic_attributes: Vec<String>,
+
+    /// Attributes which contain URLs.  Their values must be relative URLs, or use one of
+    /// `url_schemes`.
+    pub url_attributes: Vec<String>,
+
+    /// The URL schemes allowed in `url_attributes`, without the trailing `:`.
+    pub url_schemes: Vec<String>,
+}
+
+impl Default for SanitizePolicy {
+    /// A policy allowing common formatting and structural tags, similar to GitHub's.
+    fn default() -> SanitizePolicy {
+        let mut tags = HashMap::new();
+        for tag in &[
+            "abbr", "b", "br", "code", "dd", "div", "dl", "dt", "em", "h1", "h2", "h3", "h4", "h5",
+            "h6", "hr", "i", "kbd", "li", "mark", "p", "pre", "rp", "rt", "ruby", "s", "samp",
+            "small", "span", "strike", "strong", "sub", "summary", "sup", "table", "tbody", "tfoot",
+            "thead", "tr", "tt", "u", "ul", "var",
+        ] {
+            tags.insert(tag.to_string(), vec![]);
+        }
+        for &(tag, attributes) in &[
+            ("a", &["href"][..]),
+            ("img", &["src", "alt", "width", "height"][..]),
+            ("blockquote", &["cite"][..]),
+            ("q", &["cite"][..]),
+            ("del", &["cite"][..]),
+            ("ins", &["cite"][..]),
+            ("details", &["open"][..]),
+            ("ol", &["start", "type"][..]),
+            ("td", &["align", "colspan", "rowspan"][..]),
+            ("th", &["align", "colspan", "rowspan"][..]),
+        ] {
+            tags.insert(
+                tag.to_string(),
+                attributes.iter().map(|a| a.to_string()).collect(),
+            );
+        }
+
+        SanitizePolicy {
+            tags: tags,
+            generic_attributes: vec!["title".to_string(), "lang".to_string(), "dir".to_string()],
+            url_attributes: vec!["href".to_string(), "src".to_string(), "cite".to_string()],
+            url_schemes: vec![
+                "http".to_string(),
+                "https".to_string(),
+                "mailto".to_string(),
+            ],
+        }
+    }
+}
+
+/// Elements whose content is not Markdown-visible text, and is removed along with the tags.
+const RAW_TEXT_TAGS: [&str; 10] = [
+    "script",
+    "style",
+    "textarea",
+    "title",
+    "xmp",
+    "iframe",
+    "noembed",
+    "noframes",
+    "noscript",
+    "plaintext",
+];
+
+struct Tag<'i> {
+    closing: bool,
+    name: String,
+    attributes: Vec<Attribute<'i>>,
+    self_closing: bool,
+    len: usize,
+}
+
+struct Attribute<'i> {
+    name: String,
+    value: Option<&'i [u8]>,
+}
+
+/// Sanitizes a fragment of raw HTML, the contents of an `HtmlBlock` or `HtmlInline`.
+pub fn sanitize_html(input: &[u8], policy: &SanitizePolicy) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut skip_until: Option<String> = None;
+    let len = input.len();
+    let mut i = 0;
+
+    while i < len {
+        if input[i] != b'<' {
+            let end = input[i..]
+                .iter()
+                .position(|&c| c == b'<')
+                .map_or(len, |p| i + p);
+            if skip_until.is_none() {
+                output.extend_from_slice(&input[i..end]);
+            }
+            i = end;
+            continue;
+        }
+
+        let rest = &input[i..];
+        if rest.starts_with(b"<!--") {
+            i += find(&rest[4..], b"-->").map_or(rest.len(), |p| p + 7);
+            continue;
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"<?") {
+            i += find(rest, b">").map_or(rest.len(), |p| p + 1);
+            continue;
+        }
+
+        let tag = match parse_tag(rest) {
+            Some(tag) => tag,
+            None => {
+                if skip_until.is_none() {
+                    output.extend_from_slice(b"&lt;");
+                }
+                i += 1;
+                continue;
+            }
+        };
+        i += tag.len;
+
+        if skip_until.is_some() {
+            if tag.closing && skip_until.as_ref() == Some(&tag.name) {
+                skip_until = None;
+            }
+            continue;
+        }
+
+        let allowed = match policy.tags.get(&tag.name) {
+            Some(allowed) => allowed,
+            None => {
+                if !tag.closing && RAW_TEXT_TAGS.contains(&tag.name.as_str()) {
+                    skip_until = Some(tag.name);
+                }
+                continue;
+            }
+        };
+
+        output.push(b'<');
+        if tag.closing {
+            output.push(b'/');
+        }
+        output.extend_from_slice(tag.name.as_bytes());
+        if !tag.closing {
+            for attribute in &tag.attributes {
+                if !attribute_allowed(policy, allowed, &attribute.name, attribute.value) {
+                    continue;
+                }
+
+                output.push(b' ');
+                output.extend_from_slice(attribute.name.as_bytes());
+                if let Some(value) = attribute.value {
+                    output.extend_from_slice(b"=\"");
+                    for &c in value {
+                        if c == b'"' {
+                            output.extend_from_slice(b"&quot;");
+                        } else {
+                            output.push(c);
+                        }
+                    }
+                    output.push(b'"');
+                }
+            }
+            if tag.self_closing {
+                output.extend_from_slice(b" /");
+            }
+        }
+        output.push(b'>');
+    }
+
+    output
+}
+
+/// Whether an attribute, whose name is in lower case, is allowed on a tag allowing the
+/// attributes `allowed`.
+pub fn attribute_allowed(
+    policy: &SanitizePolicy,
+    allowed: &[String],
+    name: &str,
+    value: Option<&[u8]>,
+) -> bool {
+    if !allowed.iter().any(|a| a == name) && !policy.generic_attributes.iter().any(|a| a == name)
+    {
+        return false;
+    }
+    !policy.url_attributes.iter().any(|a| a == name)
+        || url_allowed(value.unwrap_or(b""), &policy.url_schemes)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn parse_tag<'i>(input: &'i [u8]) -> Option<Tag<'i>> {
+    let len = input.len();
+    let mut i = 1;
+
+    let closing = input.get(i) == Some(&b'/');
+    if closing {
+        i += 1;
+    }
+
+    if i >= len || !isalpha(input[i]) {
+        return None;
+    }
+    let name_start = i;
+    while i < len && (isalnum(input[i]) || input[i] == b'-') {
+        i += 1;
+    }
+    let name = String::from_utf8_lossy(&input[name_start..i]).to_lowercase();
+
+    let mut attributes = vec![];
+    let mut self_closing = false;
+    loop {
+        let before_space = i;
+        while i < len && isspace(input[i]) {
+            i += 1;
+        }
+        if i >= len {
+            return None;
+        }
+
+        match input[i] {
+            b'>' => {
+                i += 1;
+                break;
+            }
+            b'/' if !closing && input.get(i + 1) == Some(&b'>') => {
+                self_closing = true;
+                i += 2;
+                break;
+            }
+            _ if closing || i == before_space => return None,
+            _ => (),
+        }
+
+        let attr_start = i;
+        while i < len && !isspace(input[i]) && !b"/>=\"'".contains(&input[i]) {
+            i += 1;
+        }
+        if i == attr_start {
+            return None;
+        }
+        let attr_name = String::from_utf8_lossy(&input[attr_start..i]).to_lowercase();
+
+        let mut j = i;
+        while j < len && isspace(input[j]) {
+            j += 1;
+        }
+        let mut value = None;
+        if j < len && input[j] == b'=' {
+            j += 1;
+            while j < len && isspace(input[j]) {
+                j += 1;
+            }
+            if j >= len {
+                return None;
+            }
+            if input[j] == b'"' || input[j] == b'\'' {
+                let quote = input[j];
+                let end = match input[j + 1..].iter().position(|&c| c == quote) {
+                    Some(p) => j + 1 + p,
+                    None => return None,
+                };
+                value = Some(&input[j + 1..end]);
+                i = end + 1;
+            } else {
+                let start = j;
+                while j < len && !isspace(input[j]) && input[j] != b'>' {
+                    j += 1;
+                }
+                value = Some(&input[start..j]);
+                i = j;
+            }
+        }
+
+        attributes.push(Attribute {
+            name: attr_name,
+            value: value,
+        });
+    }
+
+    Some(Tag {
+        closing: closing,
+        name: name,
+        attributes: attributes,
+        self_closing: self_closing,
+        len: i,
+    })
+}
+
+/// Whether a URL is relative, or has one of the given schemes.  Entities are decoded and
+/// whitespace and control characters removed first, as browsers do.  Browsers also decode
+/// numeric character references without a terminating `;`, so those are terminated before
+/// decoding, and any reference left undecoded makes the URL disallowed.
+fn url_allowed(value: &[u8], schemes: &[String]) -> bool {
+    let decoded = entity::unescape_html(&terminate_numeric_references(value))
+        .into_iter()
+        .filter(|&c| c > b' ')
+        .collect::<Vec<_>>();
+    if decoded.windows(2).any(|w| w == b"&#") {
+        return false;
+    }
+
+    match decoded.iter().position(|&c| c == b':' || c == b'/' || c == b'?' || c == b'#') {
+        Some(p) if decoded[p] == b':' => {
+            let scheme = String::from_utf8_lossy(&decoded[..p]).to_lowercase();
+            schemes.contains(&scheme)
+        }
+        _ => true,
+    }
+}
+
+/// Adds the `;` that numeric character references such as `&#106` may omit.
+fn terminate_numeric_references(value: &[u8]) -> Vec<u8> {
+    let mut terminated = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        terminated.push(value[i]);
+        i += 1;
+        if value[i - 1] != b'&' || value.get(i) != Some(&b'#') {
+            continue;
+        }
+
+        terminated.push(b'#');
+        i += 1;
+        let hex = value.get(i).map_or(false, |&c| c == b'x' || c == b'X');
+        if hex {
+            terminated.push(value[i]);
+            i += 1;
+        }
+        let digits = value[i..]
+            .iter()
+            .take_while(|&&c| if hex { c.is_ascii_hexdigit() } else { isdigit(c) })
+            .count();
+        terminated.extend_from_slice(&value[i..i + digits]);
+        i += digits;
+        if digits > 0 && value.get(i) != Some(&b';') {
+            terminated.push(b';');
+        }
+    }
+    terminated
+}
@@ -0,0 +1,207 @@
+//! Statistics about a document, such as for listing it in a content management system.
+//!
+//! ```
+//! # use comrak::{parse_document, Arena, ComrakOptions};
+//! use comrak::stats;
+//!
+//! let arena = Arena::new();
+//! let root = parse_document(
+//!     &arena,
+//!     "# Getting *started*\n\nInstall it with `cargo install`, then read [the docs](/docs).\n\n\
+//!      ```sh\ncomrak --help\n```\n",
+//!     &ComrakOptions::default());
+//!
+//! let stats = stats::collect(root);
+//! assert_eq!(stats.words, 9);
+//! assert_eq!(stats.reading_time(200), 1);
+//! assert_eq!(stats.headings[0].text, "Getting started");
+//! assert_eq!(stats.links, 1);
+//! assert_eq!(stats.code_languages, vec!["sh"]);
+//! ```
+
+use nodes::{
+    AstNode, NodeCodeBlock, NodeFootnoteDefinition, NodeHeading, NodeLink, NodeReplacement,
+};
+use strings;
+use visit::{walk, Visitor, Walk};
+
+/// Statistics about a document, gathered by `collect`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats {
+    /// The number of words, not counting code, raw HTML or image descriptions.  Each character
+    /// of a script written without spaces, such as Chinese, counts as a word.
+    pub words: usize,
+
+    /// The headings, in document order.
+    pub headings: Vec<Heading>,
+
+    /// The URL of the first image, if any.
+    pub first_image: Option<String>,
+
+    /// The number of links, including autolinks.
+    pub links: usize,
+
+    /// The number of images.
+    pub images: usize,
+
+    /// The number of footnote definitions.
+    pub footnotes: usize,
+
+    /// The languages of fenced code blocks, from the first word of their info strings, in the
+    /// order they first appear.
+    pub code_languages: Vec<String>,
+}
+
+/// A heading in the outline of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    /// The heading's level, from 1 to 6.
+    pub level: u32,
+
+    /// The heading's text, without formatting.
+    pub text: String,
+}
+
+impl Stats {
+    /// The time in minutes it takes to read the document at `words_per_minute`, which must not
+    /// be zero, rounded up.
+    pub fn reading_time(&self, words_per_minute: usize) -> usize {
+        let minutes = self.words / words_per_minute;
+        if self.words % words_per_minute == 0 {
+            minutes
+        } else {
+            minutes + 1
+        }
+    }
+}
+
+/// Gathers statistics about the document rooted at `root`, in one pass over it.
+pub fn collect<'a>(root: &'a AstNode<'a>) -> Stats {
+    let mut collector = Collector {
+        stats: Stats::default(),
+        in_word: false,
+        in_heading: false,
+    };
+    walk(root, &mut collector);
+    collector.stats
+}
+
+struct Collector {
+    stats: Stats,
+    in_word: bool,
+    in_heading: bool,
+}
+
+impl Collector {
+    fn add_text(&mut self, text: &str) {
+        if self.in_heading {
+            if let Some(heading) = self.stats.headings.last_mut() {
+                heading.text.push_str(text);
+            }
+        }
+    }
+
+    fn break_word(&mut self) {
+        self.in_word = false;
+    }
+}
+
+impl<'a> Visitor<'a> for Collector {
+    fn enter(&mut self, node: &'a AstNode<'a>) -> Walk {
+        if node.data.borrow().value.block() {
+            self.break_word();
+        }
+        Walk::Continue
+    }
+
+    fn exit(&mut self, node: &'a AstNode<'a>) {
+        if node.data.borrow().value.block() {
+            self.break_word();
+            self.in_heading = false;
+        }
+    }
+
+    fn visit_heading(&mut self, _node: &'a AstNode<'a>, heading: &NodeHeading) -> Walk {
+        self.stats.headings.push(Heading {
+            level: heading.level,
+            text: String::new(),
+        });
+        self.in_heading = true;
+        Walk::Continue
+    }
+
+    fn visit_code_block(&mut self, _node: &'a AstNode<'a>, code_block: &NodeCodeBlock) -> Walk {
+        let info = String::from_utf8_lossy(&code_block.info);
+        if let Some(lang) = info.split_whitespace().next() {
+            if !self.stats.code_languages.iter().any(|l| l == lang) {
+                self.stats.code_languages.push(lang.to_string());
+            }
+        }
+        Walk::Continue
+    }
+
+    fn visit_footnote_definition(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _footnote_definition: &NodeFootnoteDefinition,
+    ) -> Walk {
+        self.stats.footnotes += 1;
+        Walk::Continue
+    }
+
+    fn visit_text(&mut self, _node: &'a AstNode<'a>, text: &[u8]) -> Walk {
+        let text = String::from_utf8_lossy(text);
+        // A word may be split across several text nodes, as in `foo*bar*`, so whether the
+        // previous node ended mid-word is carried over.
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.in_word = false;
+            } else if strings::is_cjk(c) {
+                self.in_word = false;
+                if c.is_alphanumeric() {
+                    self.stats.words += 1;
+                }
+            } else if c.is_alphanumeric() && !self.in_word {
+                self.in_word = true;
+                self.stats.words += 1;
+            }
+        }
+        self.add_text(&text);
+        Walk::Continue
+    }
+
+    fn visit_replacement(&mut self, node: &'a AstNode<'a>, replacement: &NodeReplacement) -> Walk {
+        self.visit_text(node, &replacement.replacement)
+    }
+
+    fn visit_softbreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        self.break_word();
+        self.add_text(" ");
+        Walk::Continue
+    }
+
+    fn visit_linebreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        self.break_word();
+        self.add_text(" ");
+        Walk::Continue
+    }
+
+    fn visit_code(&mut self, _node: &'a AstNode<'a>, literal: &[u8]) -> Walk {
+        self.break_word();
+        self.add_text(&String::from_utf8_lossy(literal));
+        Walk::Continue
+    }
+
+    fn visit_link(&mut self, _node: &'a AstNode<'a>, _link: &NodeLink) -> Walk {
+        self.stats.links += 1;
+        Walk::Continue
+    }
+
+    fn visit_image(&mut self, _node: &'a AstNode<'a>, link: &NodeLink) -> Walk {
+        self.stats.images += 1;
+        if self.stats.first_image.is_none() {
+            self.stats.first_image = Some(String::from_utf8_lossy(&link.url).into_owned());
+        }
+        Walk::SkipChildren
+    }
+}
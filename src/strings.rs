@@ -111,6 +111,42 @@ pub fn is_line_end_char(ch: u8) -> bool {
     }
 }
 
+/// The offsets of the starts of each line, where lines end in `\n`, `\r\n` or `\r`, as for the
+/// parser.  The first line always starts at 0.
+pub fn line_starts(input: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'\r' if input.get(i + 1) == Some(&b'\n') => {
+                i += 1;
+                starts.push(i + 1);
+            }
+            b'\r' | b'\n' => starts.push(i + 1),
+            _ => (),
+        }
+        i += 1;
+    }
+    starts
+}
+
+/// Whether `c` is from a script written without spaces between words, such as Chinese and
+/// Japanese, so that lines may be broken and words counted at any character.  Hangul is
+/// excluded, as Korean is written with spaces.
+pub fn is_cjk(c: char) -> bool {
+    match c as u32 {
+        0x2e80..=0x2fdf
+        | 0x3000..=0x30ff
+        | 0x3190..=0x31ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xf900..=0xfaff
+        | 0xff01..=0xff60
+        | 0x20000..=0x3ffff => true,
+        _ => false,
+    }
+}
+
 pub fn is_space_or_tab(ch: u8) -> bool {
     match ch {
         9 | 32 => true,
@@ -0,0 +1,110 @@
+//! Finding and toggling task list items in Markdown source, such as to save a checkbox ticked in
+//! rendered HTML back to the document.
+//!
+//! ```
+//! # use comrak::ComrakOptions;
+//! use comrak::tasks::toggle_task;
+//!
+//! let source = "## Launch\n\n- [x] Write it\n- [ ] Ship it\n";
+//! assert_eq!(toggle_task(source, &ComrakOptions::default(), 1).unwrap(),
+//!            "## Launch\n\n- [x] Write it\n- [x] Ship it\n");
+//! assert_eq!(toggle_task(source, &ComrakOptions::default(), 2), None);
+//! ```
+
+use nodes::{AstNode, NodeValue};
+use parser::{parse_document, ComrakOptions};
+use strings;
+use typed_arena::Arena;
+
+/// A task list item found in a document's source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskItem {
+    /// The item's index among the task list items of the document, counting from 0.  This is
+    /// the `data-task-index` attribute written with the `ext_tasklist_interactive` option.
+    pub index: usize,
+
+    /// Whether the item is checked.
+    pub checked: bool,
+
+    /// The line of the source the checkbox is on, counting from 1.
+    pub line: u32,
+
+    /// The column of the checkbox's `[` in bytes, counting from 1.
+    pub column: usize,
+
+    /// The offset in bytes of the character between the checkbox's brackets from the start of
+    /// the source.
+    pub offset: usize,
+}
+
+/// Returns the task list items of the document rooted at `root`, which was parsed from `source`
+/// with the `ext_tasklist` option.
+pub fn task_items<'a>(root: &'a AstNode<'a>, source: &str) -> Vec<TaskItem> {
+    let line_starts = strings::line_starts(source.as_bytes());
+    let mut items = vec![];
+    let mut index = 0;
+
+    for node in root.descendants() {
+        let (checked, symbol) = match node.data.borrow().value {
+            NodeValue::TaskItem(ref nti) => (nti.checked, nti.symbol),
+            _ => continue,
+        };
+        // Items whose checkbox can't be found still take an index, as in the HTML.
+        index += 1;
+        // The checkbox starts the paragraph it's in, unless reference definitions preceded it.
+        let (line, column) = match node.parent() {
+            Some(paragraph) => {
+                let ast = paragraph.data.borrow();
+                (ast.start_line, ast.start_column)
+            }
+            None => continue,
+        };
+        let start = match (line as usize)
+            .checked_sub(1)
+            .and_then(|l| line_starts.get(l))
+        {
+            Some(&start) => start,
+            None => continue,
+        };
+        let offset = start + column;
+        if column == 0
+            || source.as_bytes().get(offset - 1..offset + 2) != Some(&[b'[', symbol, b']'])
+        {
+            continue;
+        }
+
+        items.push(TaskItem {
+            index: index - 1,
+            checked: checked,
+            line: line,
+            column: column,
+            offset: offset,
+        });
+    }
+
+    items
+}
+
+/// Returns `source` with the checkbox of the task list item with the given index checked if it
+/// was unchecked, or unchecked if it was checked, or `None` if there is no such item.  The
+/// source is parsed with `options`, with the `ext_tasklist` option enabled.
+pub fn toggle_task(source: &str, options: &ComrakOptions, index: usize) -> Option<String> {
+    let mut options = options.clone();
+    options.ext_tasklist = true;
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, source, &options);
+    let item = match task_items(root, source)
+        .into_iter()
+        .find(|i| i.index == index)
+    {
+        Some(item) => item,
+        None => return None,
+    };
+
+    let mut toggled = String::with_capacity(source.len());
+    toggled.push_str(&source[..item.offset]);
+    toggled.push(if item.checked { ' ' } else { 'x' });
+    toggled.push_str(&source[item.offset + 1..]);
+    Some(toggled)
+}
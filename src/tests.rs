@@ -51,6 +51,20 @@ where
     );
 }
 
+fn commonmark_opts<F>(input: &str, expected: &str, opts: F)
+where
+    F: Fn(&mut ComrakOptions),
+{
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    opts(&mut options);
+
+    let root = parse_document(&arena, input, &options);
+    let mut output = vec![];
+    cm::format_document(root, &options, &mut output).unwrap();
+    compare_strs(&String::from_utf8(output).unwrap(), expected, "commonmark");
+}
+
 #[cfg(feature = "benchmarks")]
 #[cfg_attr(feature = "benchmarks", bench)]
 fn bench_progit(b: &mut Bencher) {
@@ -425,6 +439,618 @@ fn table() {
     );
 }
 
+#[test]
+fn wrap_unicode_commonmark() {
+    commonmark_opts(
+        "これは日本語の文章です。「括弧」も正しく扱います。\n",
+        concat!(
+            "これは日本語の文章で\n",
+            "す。「括弧」も正しく\n",
+            "扱います。\n"
+        ),
+        |opts| opts.width = 20,
+    );
+    commonmark_opts(
+        "caf\u{e9} e\u{301}e\u{301} \u{1f600}\u{1f600} end\n",
+        "caf\u{e9} e\u{301}e\u{301}\n\u{1f600}\u{1f600} end\n",
+        |opts| opts.width = 8,
+    );
+    html_opts(
+        "`日本語のコードスパンです日本語`\n",
+        "<p><code>日本語のコードスパンです日本語</code></p>\n",
+        |opts| opts.width = 12,
+    );
+}
+
+#[test]
+fn table_aligned_commonmark() {
+    commonmark_opts(
+        concat!(
+            "| a | centre | right |\n",
+            "|---|:-:|--:|\n",
+            "| 日本語 | x | `a\\|b` |\n",
+            "| c |\n"
+        ),
+        concat!(
+            "| a      | centre |  right |\n",
+            "| ------ | :----: | -----: |\n",
+            "| 日本語 |   x    | `a\\|b` |\n",
+            "| c      |        |        |\n"
+        ),
+        |opts| {
+            opts.ext_table = true;
+            opts.align_tables = true;
+        },
+    );
+}
+
+#[test]
+fn commonmark_style_options() {
+    use {CodeFenceChar, EmphasisDelimiter, HeadingStyle, ListStyleType, OrderedListNumbering};
+
+    commonmark_opts(
+        concat!(
+            "Title\n",
+            "=====\n",
+            "\n",
+            "*a* **b** c*d*e **f**g ***h***\n",
+            "\n",
+            "- x\n",
+            "- y\n",
+            "\n",
+            "3. p\n",
+            "4. q\n",
+            "\n",
+            "---\n",
+            "\n",
+            "```` py\n",
+            "~~~\n",
+            "````\n"
+        ),
+        concat!(
+            "Title\n",
+            "=====\n",
+            "\n",
+            "_a_ __b__ c*d*e **f**g ___h___\n",
+            "\n",
+            "  + x\n",
+            "  + y\n",
+            "\n",
+            "<!-- end list -->\n",
+            "\n",
+            "3.  p\n",
+            "3.  q\n",
+            "\n",
+            "_ _ _\n",
+            "\n",
+            "~~~~ py\n",
+            "~~~\n",
+            "~~~~\n"
+        ),
+        |opts| {
+            opts.list_style = ListStyleType::Plus;
+            opts.ordered_list_numbering = OrderedListNumbering::Constant;
+            opts.emph_delimiter = EmphasisDelimiter::Underscore;
+            opts.strong_delimiter = EmphasisDelimiter::Underscore;
+            opts.heading_style = HeadingStyle::Setext;
+            opts.code_fence_char = CodeFenceChar::Tilde;
+            opts.thematic_break = Some("_ _ _".to_string());
+        },
+    );
+}
+
+#[test]
+fn lint_rules() {
+    use lint::{Diagnostic, Linter};
+
+    let input = concat!(
+        "# Title\n",
+        "\n",
+        "Text with a trailing tab\t\n",
+        "see www.example.com  \n",
+        "and [](/empty).\n",
+        "\n",
+        "- one\n",
+        "\n",
+        "* two\n",
+        "\n",
+        "```\n",
+        "code with trailing space \n",
+        "```\n",
+        "\n",
+        "# Title\n"
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.width = 20;
+    let root = parse_document(&arena, input, &options);
+
+    let mut linter = Linter::new(&options);
+    assert!(linter.disable("heading-increment"));
+    assert!(!linter.disable("no-such-rule"));
+
+    let found = linter
+        .lint(root, input)
+        .into_iter()
+        .map(|d| (d.rule, d.line, d.column))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        vec![
+            ("line-length", 3, Some(21)),
+            ("trailing-spaces", 3, Some(25)),
+            ("bare-url", 4, Some(5)),
+            ("line-length", 4, Some(21)),
+            ("empty-link", 5, None),
+            ("consistent-list-marker", 9, None),
+            ("duplicate-heading", 15, None),
+        ]
+    );
+
+    let mut linter = Linter::empty();
+    linter.add_rule(Box::new(::lint::BareUrl));
+    let input = "<https://b.org> [https://x.org](https://x.org)\rgo https://a.org\n";
+    let root = parse_document(&arena, input, &options);
+    assert_eq!(
+        linter
+            .lint(root, input)
+            .into_iter()
+            .map(|d| (d.line, d.column))
+            .collect::<Vec<_>>(),
+        vec![(2, Some(4))]
+    );
+
+    let input = "[https://a.org](https://a.org) <https://a.org> `https://a.org` https://a.org\n";
+    let root = parse_document(&arena, input, &options);
+    assert_eq!(
+        linter
+            .lint(root, input)
+            .into_iter()
+            .map(|d| (d.line, d.column))
+            .collect::<Vec<_>>(),
+        vec![(1, Some(64))]
+    );
+
+    let mut linter = Linter::empty();
+    linter.add_rule(Box::new(::lint::TrailingSpaces));
+    let input = "A  \nhard break.  \n\n# Heading  \n";
+    let root = parse_document(&arena, input, &options);
+    assert_eq!(
+        linter
+            .lint(root, input)
+            .into_iter()
+            .map(|d| (d.line, d.column))
+            .collect::<Vec<_>>(),
+        vec![(2, Some(12)), (4, Some(10))]
+    );
+
+    let mut linter = Linter::empty();
+    linter.add_rule(Box::new(::lint::HeadingIncrement));
+    let root = parse_document(&arena, "# a\n\n### b\n", &options);
+    assert_eq!(
+        linter.lint(root, "# a\n\n### b\n"),
+        vec![Diagnostic {
+            rule: "heading-increment",
+            message: "heading level should be at most 2, found 3".to_string(),
+            line: 3,
+            column: None,
+        }]
+    );
+}
+
+#[test]
+fn query_selectors() {
+    use query::{select, Selector};
+
+    let input = concat!(
+        "# [Top](/top)\n",
+        "\n",
+        "## *[Nested](/nested \"t\")*\n",
+        "\n",
+        "| a | b |\n",
+        "|---|---|\n",
+        "| ![x](/x.png) | y |\n",
+        "\n",
+        "![z](/z.png)\n",
+        "\n",
+        "``` rust ignore\n",
+        "fn main() {}\n",
+        "```\n"
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_table = true;
+    let root = parse_document(&arena, input, &options);
+
+    let urls = |selector: &str| {
+        select(root, selector)
+            .unwrap()
+            .into_iter()
+            .map(|n| match n.data.borrow().value {
+                ::nodes::NodeValue::Link(ref nl) | ::nodes::NodeValue::Image(ref nl) => {
+                    String::from_utf8(nl.url.clone()).unwrap()
+                }
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(urls("heading[level=2] > link"), Vec::<String>::new());
+    assert_eq!(urls("heading[level=2] link"), vec!["/nested"]);
+    assert_eq!(urls("heading > link, heading emph > link"), vec!["/top", "/nested"]);
+    assert_eq!(urls("link[title='t']"), vec!["/nested"]);
+    assert_eq!(urls("table image"), vec!["/x.png"]);
+    assert_eq!(urls("image[url$=\".png\"]"), vec!["/x.png", "/z.png"]);
+    assert_eq!(select(root, "code_block[lang=rust]").unwrap().len(), 1);
+    assert_eq!(select(root, "code_block[info*=ignore][fenced=true]").unwrap().len(), 1);
+    assert_eq!(select(root, "table_row[header=true] > *").unwrap().len(), 2);
+
+    let error = Selector::parse("heading[level=2").unwrap_err();
+    assert_eq!(error.position, 15);
+    assert!(Selector::parse("heading[level~=2]").is_err());
+    assert!(Selector::parse("").is_err());
+    let error = select(root, "block_quote > heding").unwrap_err();
+    assert_eq!(error.message, "unknown node type 'heding'");
+    assert_eq!(error.position, 14);
+}
+
+#[test]
+fn build_and_edit_nodes() {
+    use nodes::{self, NodeBuilder, NodeHeading, NodeValue};
+
+    let arena = Arena::new();
+    let options = ComrakOptions::default();
+    let root = parse_document(&arena, "Intro with *emphasis*.\n\nOld paragraph.\n", &options);
+
+    let heading = NodeBuilder::new(
+        &arena,
+        NodeValue::Heading(NodeHeading {
+            level: 1,
+            setext: false,
+        }),
+    ).text("Title")
+        .build()
+        .unwrap();
+    root.prepend(heading);
+
+    let intro = heading.next_sibling().unwrap();
+    nodes::unwrap(intro.children().nth(1).unwrap());
+
+    let replacement = NodeBuilder::new(&arena, NodeValue::Paragraph)
+        .child(NodeBuilder::new(&arena, NodeValue::Strong).text("New").build().unwrap())
+        .build()
+        .unwrap();
+    nodes::replace(root.last_child().unwrap(), replacement);
+    nodes::wrap(replacement, nodes::new_node(&arena, NodeValue::BlockQuote));
+    assert!(nodes::validate(root).is_ok());
+
+    let err = nodes::append_checked(heading, nodes::new_node(&arena, NodeValue::Paragraph))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "heading cannot contain paragraph");
+    assert!(heading.children().count() == 1);
+
+    let mut output = vec![];
+    html::format_document(root, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "<h1>Title</h1>\n",
+            "<p>Intro with emphasis.</p>\n",
+            "<blockquote>\n",
+            "<p><strong>New</strong></p>\n",
+            "</blockquote>\n"
+        ),
+        "html",
+    );
+}
+
+#[test]
+fn visit_and_transform() {
+    use nodes::{AstNode, NodeLink, NodeValue};
+    use typed_arena::Arena;
+    use visit::{transform, walk, Transformer, Visitor, Walk};
+
+    struct Outline(Vec<String>);
+
+    impl<'a> Visitor<'a> for Outline {
+        fn enter(&mut self, node: &'a AstNode<'a>) -> Walk {
+            match node.data.borrow().value {
+                NodeValue::CodeBlock(..) => Walk::Stop,
+                NodeValue::Paragraph => Walk::SkipChildren,
+                _ => Walk::Continue,
+            }
+        }
+
+        fn visit_text(&mut self, _node: &'a AstNode<'a>, text: &[u8]) -> Walk {
+            self.0.push(String::from_utf8(text.to_vec()).unwrap());
+            Walk::Continue
+        }
+
+        fn exit(&mut self, node: &'a AstNode<'a>) {
+            if let NodeValue::Heading(..) = node.data.borrow().value {
+                self.0.push("/".to_string());
+            }
+        }
+    }
+
+    struct Rewrite;
+
+    impl<'a> Transformer<'a> for Rewrite {
+        fn transform_link(&mut self, _node: &'a AstNode<'a>, link: &mut NodeLink) -> Walk {
+            if link.url.ends_with(b".md") {
+                let len = link.url.len();
+                link.url.truncate(len - 3);
+                link.url.extend_from_slice(b".html");
+            }
+            Walk::Continue
+        }
+
+        fn exit(&mut self, node: &'a AstNode<'a>) {
+            if node.data.borrow().value == NodeValue::Emph {
+                ::nodes::unwrap(node);
+            }
+        }
+    }
+
+    fn rewrite<'a>(_arena: &'a Arena<AstNode<'a>>, root: &'a AstNode<'a>) {
+        transform(root, &mut Rewrite);
+    }
+
+    let arena = Arena::new();
+    let root = parse_document(
+        &arena,
+        "# One *two*\n\nSkipped\n\n## Three\n\n```\ncode\n```\n\n# Four\n",
+        &ComrakOptions::default(),
+    );
+    let mut outline = Outline(vec![]);
+    walk(root, &mut outline);
+    assert_eq!(outline.0, vec!["One ", "two", "/", "Three", "/"]);
+
+    html_opts(
+        "*[a](a.md)* and *[b](b.md) **c***\n",
+        "<p><a href=\"a.html\">a</a> and <a href=\"b.html\">b</a> <strong>c</strong></p>\n",
+        |opts| opts.transforms = vec![::std::sync::Arc::new(rewrite)],
+    );
+
+    struct Prune;
+
+    impl<'a> Transformer<'a> for Prune {
+        fn enter(&mut self, node: &'a AstNode<'a>) -> Walk {
+            if node.data.borrow().value == NodeValue::Emph {
+                node.detach();
+            }
+            Walk::Continue
+        }
+
+        fn transform_text(&mut self, node: &'a AstNode<'a>, text: &mut Vec<u8>) -> Walk {
+            if node.data.borrow().value == NodeValue::Text(b"drop".to_vec()) {
+                node.detach();
+            }
+            text.make_ascii_uppercase();
+            Walk::Continue
+        }
+    }
+
+    let root = parse_document(&arena, "*a* b *c* d\n\n*e*\n\n**drop** f\n", &Default::default());
+    transform(root, &mut Prune);
+    let mut output = vec![];
+    html::format_document(root, &ComrakOptions::default(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "<p> B  D</p>\n<p></p>\n<p><strong></strong> F</p>\n"
+    );
+}
+
+#[test]
+fn parse_fragments() {
+    use nodes::{new_node, NodeValue};
+    use {parse_document_with_references, parse_fragment, parse_inline, ReferenceMap};
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_autolink = true;
+    let mut refmap = ReferenceMap::default();
+    refmap.insert("Docs", "/docs", "");
+    let root = parse_document_with_references(
+        &arena,
+        "> First\n\n[Home]: /home\n",
+        &options,
+        &mut refmap,
+    );
+    assert!(refmap.contains("home") && refmap.contains("DOCS"));
+    assert_eq!(refmap.len(), 2);
+
+    parse_fragment(
+        &arena,
+        root.first_child().unwrap(),
+        "[home], [docs] and www.example.com\n\n[away]: /away\n",
+        &options,
+        Some(&mut refmap),
+    ).unwrap();
+    assert!(refmap.contains("away"));
+
+    let paragraph = new_node(&arena, NodeValue::Paragraph);
+    root.append(paragraph);
+    parse_inline(&arena, paragraph, "[away] *now*\n[gone]", &options, Some(&mut refmap)).unwrap();
+    assert!(parse_inline(&arena, root, "text", &options, None).is_err());
+    assert!(parse_fragment(&arena, paragraph, "# Heading", &options, None).is_err());
+    let heading = "# Heading\n\n[gone]: /gone\n";
+    assert!(parse_fragment(&arena, paragraph, heading, &options, Some(&mut refmap)).is_err());
+    assert!(!refmap.contains("gone"));
+
+    let mut output = vec![];
+    html::format_document(root, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "<blockquote>\n",
+            "<p>First</p>\n",
+            "<p><a href=\"/home\">home</a>, <a href=\"/docs\">docs</a> and ",
+            "<a href=\"http://www.example.com\">www.example.com</a></p>\n",
+            "</blockquote>\n",
+            "<p><a href=\"/away\">away</a> <em>now</em>\n[gone]</p>\n"
+        ),
+        "html",
+    );
+}
+
+#[test]
+fn document_stats() {
+    use stats::{self, Heading};
+
+    let input = concat!(
+        "# Intro to *comrak*\n",
+        "\n",
+        "It's fast[^1] — see <https://example.com> and ![the logo](logo.png).\n",
+        "Use `cargo`; or `make`.\n",
+        "\n",
+        "## Usage\n",
+        "\n",
+        "```rust\n",
+        "let x = 1;\n",
+        "```\n",
+        "\n",
+        "```\n",
+        "plain\n",
+        "```\n",
+        "\n",
+        "    indented\n",
+        "\n",
+        "<div>raw html</div>\n",
+        "\n",
+        "[^1]: Citation needed.\n"
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_footnotes = true;
+    let root = parse_document(&arena, input, &options);
+    let stats = stats::collect(root);
+
+    assert_eq!(stats.words, 13);
+    assert_eq!(
+        stats.headings,
+        vec![
+            Heading {
+                level: 1,
+                text: "Intro to comrak".to_string(),
+            },
+            Heading {
+                level: 2,
+                text: "Usage".to_string(),
+            },
+        ]
+    );
+    assert_eq!(stats.first_image, Some("logo.png".to_string()));
+    assert_eq!((stats.links, stats.images, stats.footnotes), (1, 1, 1));
+    assert_eq!(stats.code_languages, vec!["rust"]);
+    assert_eq!(stats.reading_time(5), 3);
+}
+
+#[test]
+fn document_stats_cjk() {
+    use stats;
+
+    let arena = Arena::new();
+    let root = parse_document(
+        &arena,
+        "日本語の文章です。Rust は速い、한국어 단어\n",
+        &ComrakOptions::default(),
+    );
+    assert_eq!(stats::collect(root).words, 14);
+}
+
+#[test]
+fn lossless_roundtrip() {
+    use nodes::NodeValue;
+    use parse_document_lossless;
+
+    let input = concat!(
+        "Title\r\n",
+        "=====\r\n",
+        "\n",
+        "\n",
+        "+ one\\*\n",
+        "+ two &copy;\n",
+        "\n",
+        "[ref]: /url\n",
+        "\n",
+        "Hi[^note] *there*\n",
+        "\n",
+        "[^note]: A note.\n",
+        "\n",
+        "  > quoted\n",
+        "lazily\n",
+        "\n",
+        "~~~~\n",
+        "code\n",
+        "~~~~"
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_footnotes = true;
+    let doc = parse_document_lossless(&arena, input, &options);
+
+    let mut output = vec![];
+    doc.format(&options, &mut output).unwrap();
+    compare_strs(&String::from_utf8(output).unwrap(), input, "lossless");
+
+    let paragraph = doc.root().children().nth(2).unwrap();
+    let emph = paragraph.last_child().unwrap();
+    emph.data.borrow_mut().value = NodeValue::Strong;
+
+    let mut output = vec![];
+    doc.format(&options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        &input.replace("Hi[^note] *there*", "Hi[^note] **there**"),
+        "lossless",
+    );
+
+    // A change within a top-level block renders the whole block with the formatter.
+    let list = doc.root().children().nth(1).unwrap();
+    assert_eq!(list.data.borrow().end_line(), 7);
+    let text = list.last_child().unwrap().first_child().unwrap().first_child().unwrap();
+    text.data.borrow_mut().value = NodeValue::Text(b"three".to_vec());
+
+    let mut output = vec![];
+    doc.format(&options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        &input
+            .replace("Hi[^note] *there*", "Hi[^note] **there**")
+            .replace("+ one\\*\n+ two &copy;\n", "  - one\\*\n  - three\n"),
+        "lossless",
+    );
+}
+
+#[test]
+fn lossless_insertions() {
+    use parse_document_lossless;
+
+    let arena = Arena::new();
+    let options = ComrakOptions::default();
+    let insert_after = |input: &str, insertion: &str| {
+        let doc = parse_document_lossless(&arena, input, &options);
+        let block = parse_document(&arena, insertion, &options).first_child().unwrap();
+        doc.root().first_child().unwrap().insert_after(block);
+
+        let mut output = vec![];
+        doc.format(&options, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(insert_after("*a*", "b\n"), "*a*\n\nb\n");
+    assert_eq!(
+        insert_after("* a\n", "- b\n"),
+        "  - a\n\n<!-- end list -->\n\n  - b\n"
+    );
+    assert_eq!(insert_after("* a\n\nc\n", "b\n"), "* a\n\nb\n\nc\n");
+}
+
 #[test]
 fn autolink_www() {
     html_opts(
@@ -468,6 +1094,75 @@ fn autolink_scheme_multiline() {
     );
 }
 
+#[test]
+fn autolink_configured() {
+    fn filter(url: &str) -> Option<String> {
+        if url.starts_with("ticket://") {
+            Some(url.replace("ticket://", "https://tickets.example.com/"))
+        } else if url.contains("blocked") {
+            None
+        } else {
+            Some(url.to_string())
+        }
+    }
+
+    html_opts(
+        concat!(
+            "jira:PROJ-123, ticket://intranet_host/9 and www.blocked.com.\n",
+            "\n",
+            "http://x.com jira: alone\n",
+            "\n",
+            "svn+ssh://host/repo (ms-settings:display, not xms-settings:y)\n"
+        ),
+        concat!(
+            "<p><a href=\"jira:PROJ-123\">jira:PROJ-123</a>, \
+             <a href=\"https://tickets.example.com/intranet_host/9\">ticket://intranet_host/9</a> \
+             and www.blocked.com.</p>\n",
+            "<p>http://x.com jira: alone</p>\n",
+            "<p><a href=\"svn+ssh://host/repo\">svn+ssh://host/repo</a> \
+             (<a href=\"ms-settings:display\">ms-settings:display</a>, not xms-settings:y)</p>\n"
+        ),
+        |opts| {
+            opts.ext_autolink = true;
+            opts.ext_autolink_schemes = vec![
+                "jira:".to_string(),
+                "ticket://".to_string(),
+                "svn+ssh://".to_string(),
+                "ms-settings:".to_string(),
+            ];
+            opts.ext_autolink_relaxed = true;
+            opts.ext_autolink_filter = Some(::std::sync::Arc::new(filter));
+        },
+    );
+}
+
+#[test]
+fn references() {
+    html_opts(
+        concat!(
+            "Fixes #12 and a#13, per @alice-b (cc @-x, a@b.co) in 3f2a9c1e.\n",
+            "\n",
+            "[see #14](/x) `#15` deadbeef 1234567 deadbee7x #16a\n",
+            "\n",
+            "修正#17 www.example.com/#18\n"
+        ),
+        concat!(
+            "<p>Fixes <a href=\"/i/12\">#12</a> and a#13, per \
+             <a href=\"/u/alice-b\">@alice-b</a> (cc @-x, <a href=\"mailto:a@b.co\">a@b.co</a>) in \
+             <a href=\"/c/3f2a9c1e\">3f2a9c1e</a>.</p>\n",
+            "<p><a href=\"/x\">see #14</a> <code>#15</code> deadbeef 1234567 deadbee7x #16a</p>\n",
+            "<p>修正<a href=\"/i/17\">#17</a> \
+             <a href=\"http://www.example.com/#18\">www.example.com/#18</a></p>\n"
+        ),
+        |opts| {
+            opts.ext_autolink = true;
+            opts.ext_issue_url = Some("/i/{}".to_string());
+            opts.ext_mention_url = Some("/u/{}".to_string());
+            opts.ext_commit_url = Some("/c/{}".to_string());
+        },
+    );
+}
+
 #[test]
 fn autolink_no_link_bad() {
     html_opts(
@@ -477,6 +1172,106 @@ fn autolink_no_link_bad() {
     );
 }
 
+#[test]
+fn sanitize() {
+    html_opts(
+        concat!(
+            "<div class=\"x\" title='a \"b\"'>\n",
+            "<script>alert(1)</script><!-- note --><img src=\"jav&#x61;script:x\" alt=x onerror=y />\n",
+            "<p><a href=\"/rel\" target=_blank>ok</a> <a href=\"HTTPS://x.org\">ok</a></p>\n",
+            "</div>\n",
+            "\n",
+            "Inline <sup>1</sup><marquee>x</marquee> <a href=\"data:text/html,x\">y</a> a < b\n",
+            "\n",
+            "<a href=\"&#106avascript:alert(1)\">x</a> <a href=\"&#x6A&#x61vascript:x\">y</a>\n",
+            "<a href=\"&#00000000106;avascript:x\">z</a>\n"
+        ),
+        concat!(
+            "<div title=\"a &quot;b&quot;\">\n",
+            "<img alt=\"x\" />\n",
+            "<p><a href=\"/rel\">ok</a> <a href=\"HTTPS://x.org\">ok</a></p>\n",
+            "</div>\n",
+            "<p>Inline <sup>1</sup>x <a>y</a> a &lt; b</p>\n",
+            "<p><a>x</a> <a>y</a>\n",
+            "<a>z</a></p>\n"
+        ),
+        |opts| opts.sanitize = Some(::SanitizePolicy::default()),
+    );
+}
+
+#[test]
+fn url_policy() {
+    use UrlPolicy;
+
+    let policy = UrlPolicy {
+        base_url: Some("https://example.com/docs/guide/intro.html?v=1#top".to_string()),
+        external_link_target: Some("_blank".to_string()),
+        image_hosts: vec!["example.com".to_string()],
+        ..UrlPolicy::default()
+    };
+
+    html_opts(
+        concat!(
+            "[a](setup.html) [b](/root) [c](?v=2) [d](#part) [e](//cdn.org/x) ",
+            "[f](https://EXAMPLE.com/y) [g](mailto:me@x.org) [k](https:evil.org)\n",
+            "\n",
+            "![h](img/h.png) ![i](http://other.org/i.png) ![j](https:evil.org/j.png)\n"
+        ),
+        concat!(
+            "<p><a href=\"https://example.com/docs/guide/setup.html\">a</a> ",
+            "<a href=\"https://example.com/root\">b</a> ",
+            "<a href=\"https://example.com/docs/guide/intro.html?v=2\">c</a> ",
+            "<a href=\"#part\">d</a> ",
+            "<a href=\"https://cdn.org/x\" target=\"_blank\">e</a> ",
+            "<a href=\"https://EXAMPLE.com/y\">f</a> ",
+            "<a href=\"mailto:me@x.org\">g</a> ",
+            "<a href=\"https:evil.org\" target=\"_blank\">k</a></p>\n",
+            "<p><img src=\"https://example.com/docs/guide/img/h.png\" alt=\"h\" /> ",
+            "<img src=\"\" alt=\"i\" /> <img src=\"\" alt=\"j\" /></p>\n"
+        ),
+        |opts| opts.url_policy = Some(policy.clone()),
+    );
+
+    let policy = UrlPolicy {
+        image_proxy: Some("/proxy?u={}".to_string()),
+        ..policy
+    };
+    assert_eq!(
+        policy.image_url("HTTPS:evil.org/j.png").unwrap(),
+        "/proxy?u=HTTPS%3Aevil.org%2Fj.png"
+    );
+}
+
+#[test]
+fn url_rewriter() {
+    use nodes::{AstNode, NodeValue};
+
+    fn rewrite<'a>(node: &'a AstNode<'a>, url: &str) -> String {
+        let in_link = node.ancestors().skip(1).any(|n| match n.data.borrow().value {
+            NodeValue::Link(..) => true,
+            _ => false,
+        });
+        match node.data.borrow().value {
+            NodeValue::Image(..) if in_link => format!("/thumbs/{}", url),
+            NodeValue::Link(..) if url == "evil" => "javascript:alert(1)".to_string(),
+            _ => url.replace(".md", ".html"),
+        }
+    }
+
+    html_opts(
+        "[a](x.md) [![b](b.png)](big.png) [c](evil) ![d](d.png)\n",
+        concat!(
+            "<p><a href=\"x.html\">a</a> ",
+            "<a href=\"big.png\"><img src=\"/thumbs/b.png\" alt=\"b\" /></a> ",
+            "<a href=\"\">c</a> <img src=\"d.png\" alt=\"d\" /></p>\n"
+        ),
+        |opts| {
+            opts.safe = true;
+            opts.url_rewriter = Some(::std::sync::Arc::new(rewrite));
+        },
+    );
+}
+
 #[test]
 fn tagfilter() {
     html_opts(
@@ -550,6 +1345,134 @@ fn tasklist_32() {
     );
 }
 
+#[test]
+fn tasklist_nodes() {
+    use query::select;
+
+    let input = concat!("- [ ] Open\n", "- [X] Done\n", "- [x] Also done\n", "  - [ ] Nested\n");
+    commonmark_opts(
+        input,
+        concat!(
+            "  - [ ] Open\n",
+            "  - [X] Done\n",
+            "  - [x] Also done\n",
+            "      - [ ] Nested\n"
+        ),
+        |opts| opts.ext_tasklist = true,
+    );
+    html_opts(
+        "- [ ] a <b>\n- [X] c\n",
+        concat!(
+            "<ul>\n",
+            "<li><input type=\"checkbox\" disabled=\"\" /> a <!-- raw HTML omitted --></li>\n",
+            "<li><input type=\"checkbox\" disabled=\"\" checked=\"\" /> c</li>\n",
+            "</ul>\n"
+        ),
+        |opts| {
+            opts.ext_tasklist = true;
+            opts.safe = true;
+        },
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_tasklist = true;
+    let root = parse_document(&arena, input, &options);
+    assert_eq!(select(root, "task_item").unwrap().len(), 4);
+    assert_eq!(select(root, "task_item[checked=false]").unwrap().len(), 2);
+
+    for node in root.descendants() {
+        if let ::nodes::NodeValue::TaskItem(ref mut nti) = node.data.borrow_mut().value {
+            nti.checked = !nti.checked;
+        }
+    }
+    let mut output = vec![];
+    cm::format_document(root, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "  - [x] Open\n",
+            "  - [ ] Done\n",
+            "  - [ ] Also done\n",
+            "      - [x] Nested\n"
+        ),
+        "commonmark",
+    );
+}
+
+#[test]
+fn tasklist_toggle() {
+    use tasks::{task_items, toggle_task, TaskItem};
+
+    let source = "Intro\r\n\r\n> 1. [X] One\r\n>    - [ ] Two\r\n\r\n- [ ] [Three](/3)\r\n";
+    let mut options = ComrakOptions::default();
+    options.ext_tasklist = true;
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, source, &options);
+    let items = task_items(root, source);
+    assert_eq!(
+        items[1],
+        TaskItem {
+            index: 1,
+            checked: false,
+            line: 4,
+            column: 8,
+            offset: 31,
+        }
+    );
+    assert_eq!(
+        items.iter().map(|i| (i.line, i.checked)).collect::<Vec<_>>(),
+        vec![(3, true), (4, false), (6, false)]
+    );
+
+    assert_eq!(
+        toggle_task(source, &options, 0).unwrap(),
+        "Intro\r\n\r\n> 1. [ ] One\r\n>    - [ ] Two\r\n\r\n- [ ] [Three](/3)\r\n"
+    );
+    assert_eq!(
+        toggle_task(source, &ComrakOptions::default(), 2).unwrap(),
+        "Intro\r\n\r\n> 1. [X] One\r\n>    - [ ] Two\r\n\r\n- [x] [Three](/3)\r\n"
+    );
+    assert_eq!(toggle_task(source, &options, 3), None);
+
+    options.ext_footnotes = true;
+    assert_eq!(
+        toggle_task("Note[^n]\n\n[^n]: - [ ] x\n", &options, 0).unwrap(),
+        "Note[^n]\n\n[^n]: - [x] x\n"
+    );
+
+    // The checkbox after a reference definition can't be found, but keeps its index.
+    let source = "- [a]: /u\n  [ ] b\n- [ ] c\n";
+    let root = parse_document(&arena, source, &options);
+    assert_eq!(
+        task_items(root, source)
+            .iter()
+            .map(|i| (i.index, i.line))
+            .collect::<Vec<_>>(),
+        vec![(1, 3)]
+    );
+    assert_eq!(toggle_task(source, &options, 0), None);
+    assert_eq!(
+        toggle_task(source, &options, 1).unwrap(),
+        "- [a]: /u\n  [ ] b\n- [x] c\n"
+    );
+
+    html_opts(
+        "- [ ] a\n- [x] b\n",
+        concat!(
+            "<ul>\n",
+            "<li><input type=\"checkbox\" data-task-index=\"0\" /> a</li>\n",
+            "<li><input type=\"checkbox\" data-task-index=\"1\" checked=\"\" /> b</li>\n",
+            "</ul>\n"
+        ),
+        |opts| {
+            opts.ext_tasklist = true;
+            opts.ext_tasklist_interactive = true;
+        },
+    );
+}
+
 #[test]
 fn superscript() {
     html_opts(
@@ -559,6 +1482,261 @@ fn superscript() {
     );
 }
 
+#[test]
+fn subscript_highlight_insert_underline() {
+    html_opts(
+        "H~2~O is ~~not~~ ~wet, ==really== ++new++ and __underlined__, **strong** and _em_.\n",
+        "<p>H<sub>2</sub>O is <del>not</del> ~wet, <mark>really</mark> <ins>new</ins> and \
+         <u>underlined</u>, <strong>strong</strong> and <em>em</em>.</p>\n",
+        |opts| {
+            opts.ext_subscript = true;
+            opts.ext_strikethrough = true;
+            opts.ext_highlight = true;
+            opts.ext_insert = true;
+            opts.ext_underline = true;
+        },
+    );
+
+    html_opts(
+        "H~2~O ~~dry~~ =x= +y+ __strong__\n",
+        "<p>H<sub>2</sub>O ~~dry~~ =x= +y+ <strong>strong</strong></p>\n",
+        |opts| {
+            opts.ext_subscript = true;
+            opts.ext_highlight = true;
+            opts.ext_insert = true;
+        },
+    );
+
+    commonmark_opts(
+        "H~2~O ~~dry~~ ==hi== ++in++ __u__ **s**\n",
+        "H~2~O ~~dry~~ ==hi== ++in++ __u__ **s**\n",
+        |opts| {
+            opts.ext_subscript = true;
+            opts.ext_strikethrough = true;
+            opts.ext_highlight = true;
+            opts.ext_insert = true;
+            opts.ext_underline = true;
+            opts.strong_delimiter = ::EmphasisDelimiter::Underscore;
+        },
+    );
+    html_opts(
+        "Not \\~y\\~, a\\=\\=b\\=\\= or \\+\\+c\\+\\+; https://x.org/?a==b==c ++d++\n",
+        "<p>Not ~y~, a==b== or ++c++; \
+         <a href=\"https://x.org/?a==b==c\">https://x.org/?a==b==c</a> <ins>d</ins></p>\n",
+        |opts| {
+            opts.ext_subscript = true;
+            opts.ext_highlight = true;
+            opts.ext_insert = true;
+            opts.ext_autolink = true;
+        },
+    );
+
+    commonmark_opts(
+        "Not \\~y\\~, a\\=\\=b\\=\\= or \\+\\+c\\+\\+, 1+1=2.\n",
+        "Not \\~y\\~, a\\==b\\== or \\++c\\++, 1+1=2.\n",
+        |opts| {
+            opts.ext_subscript = true;
+            opts.ext_highlight = true;
+            opts.ext_insert = true;
+        },
+    );
+}
+
+#[test]
+fn spoilers() {
+    html_opts(
+        concat!(
+            "It was ||the *butler*|| and |not| this.\n",
+            "\n",
+            ">! The butler\n",
+            "did it. !<\n",
+            "\n",
+            ">!> Quoted\n",
+            ">!\n",
+            ">! - item\n",
+            "\n",
+            "> ! Not a spoiler\n"
+        ),
+        concat!(
+            "<p>It was <span class=\"spoiler\">the <em>butler</em></span> and |not| this.</p>\n",
+            "<details class=\"spoiler\">\n",
+            "<p>The butler\n",
+            "did it.</p>\n",
+            "</details>\n",
+            "<details class=\"spoiler\">\n",
+            "<blockquote>\n",
+            "<p>Quoted</p>\n",
+            "</blockquote>\n",
+            "<ul>\n",
+            "<li>item</li>\n",
+            "</ul>\n",
+            "</details>\n",
+            "<blockquote>\n",
+            "<p>! Not a spoiler</p>\n",
+            "</blockquote>\n"
+        ),
+        |opts| opts.ext_spoiler = true,
+    );
+
+    html_opts(
+        concat!(
+            ">! hidden !<\n",
+            "visible\n",
+            "\n",
+            ">! a !<\n",
+            ">! b\n",
+            "\n",
+            ">! - item !<\n",
+            "\n",
+            ">! ```\n",
+            ">! code !<\n",
+            ">! ```\n",
+            "\n",
+            ">![quoted](x.png)\n"
+        ),
+        concat!(
+            "<details class=\"spoiler\">\n",
+            "<p>hidden</p>\n",
+            "</details>\n",
+            "<p>visible</p>\n",
+            "<details class=\"spoiler\">\n",
+            "<p>a</p>\n",
+            "</details>\n",
+            "<details class=\"spoiler\">\n",
+            "<p>b</p>\n",
+            "</details>\n",
+            "<details class=\"spoiler\">\n",
+            "<ul>\n",
+            "<li>item</li>\n",
+            "</ul>\n",
+            "</details>\n",
+            "<details class=\"spoiler\">\n",
+            "<pre><code>code !&lt;\n",
+            "</code></pre>\n",
+            "</details>\n",
+            "<blockquote>\n",
+            "<p><img src=\"x.png\" alt=\"quoted\" /></p>\n",
+            "</blockquote>\n"
+        ),
+        |opts| opts.ext_spoiler = true,
+    );
+
+    html(
+        ">! Not a spoiler ||either||\n",
+        "<blockquote>\n<p>! Not a spoiler ||either||</p>\n</blockquote>\n",
+    );
+}
+
+#[test]
+fn containers() {
+    html_opts(
+        concat!(
+            "::: warning {#backup .wide hidden} Heads up :::\n",
+            "Back up *first*.\n",
+            "\n",
+            ":::: tabs\n",
+            "::: tab {title=\"One\"}\n",
+            "```\n",
+            ":::\n",
+            "```\n",
+            ":::\n",
+            "::::\n",
+            "still a warning\n",
+            ":::\n",
+            "\n",
+            ":::\n",
+            "not a container\n"
+        ),
+        concat!(
+            "<div class=\"warning wide\" id=\"backup\" hidden=\"\">\n",
+            "<p>Back up <em>first</em>.</p>\n",
+            "<div class=\"tabs\">\n",
+            "<div class=\"tab\" title=\"One\">\n",
+            "<pre><code>:::\n",
+            "</code></pre>\n",
+            "</div>\n",
+            "</div>\n",
+            "<p>still a warning</p>\n",
+            "</div>\n",
+            "<p>:::\n",
+            "not a container</p>\n"
+        ),
+        |opts| opts.ext_container = true,
+    );
+
+    html_opts(
+        concat!(
+            "::: x {#i .c onclick=\"alert(1)\" style=\"position:fixed\" title=\"T\" a\"b=1}\n",
+            "Text\n",
+            ":::\n"
+        ),
+        "<div class=\"x c\" title=\"T\">\n<p>Text</p>\n</div>\n",
+        |opts| {
+            opts.ext_container = true;
+            opts.sanitize = Some(::SanitizePolicy::default());
+        },
+    );
+
+    html_opts(
+        "::: x {#i onclick=\"alert(1)\" a\"b=1}\nText\n:::\n",
+        "<div class=\"x\" id=\"i\">\n<p>Text</p>\n</div>\n",
+        |opts| {
+            opts.ext_container = true;
+            opts.safe = true;
+        },
+    );
+
+    commonmark_opts(
+        ":::: note {#n .a key=\"v w\"} Title\nText\n::::\n",
+        ":::: note {#n .a key=\"v w\"} Title\n\nText\n\n::::\n",
+        |opts| opts.ext_container = true,
+    );
+
+    commonmark_opts(
+        "> ::: note\n> Quoted\n> :::\n\n- ::: tip\n  Listed\n  :::\n",
+        "> ::: note\n> \n> Quoted\n> \n> :::\n\n  - ::: tip\n    \n    Listed\n    :::\n",
+        |opts| opts.ext_container = true,
+    );
+
+    html(
+        "::: warning\nText\n:::\n",
+        "<p>::: warning\nText\n:::</p>\n",
+    );
+}
+
+#[test]
+fn abbreviations() {
+    html_opts(
+        concat!(
+            "*[HTML]: Hyper Text Markup Language\n",
+            "*[W3C]:  World Wide Web Consortium\n",
+            "The HTML spec is by the W3C, not HTML5 or [HTML](/html) or `HTML`.\n",
+            "\n",
+            "*[HTML 5]: The fifth \"HTML\"\n",
+            "HTML 5 is here.\n"
+        ),
+        concat!(
+            "<p>The <abbr title=\"Hyper Text Markup Language\">HTML</abbr> spec is by the ",
+            "<abbr title=\"World Wide Web Consortium\">W3C</abbr>, not HTML5 or ",
+            "<a href=\"/html\"><abbr title=\"Hyper Text Markup Language\">HTML</abbr></a> or ",
+            "<code>HTML</code>.</p>\n",
+            "<p><abbr title=\"The fifth &quot;HTML&quot;\">HTML 5</abbr> is here.</p>\n"
+        ),
+        |opts| opts.ext_abbreviations = true,
+    );
+
+    commonmark_opts(
+        "HTML and CSS.\n\n*[CSS]: Cascading Style Sheets\n*[HTML]: Hyper Text Markup Language\n",
+        "HTML and CSS.\n\n*[HTML]: Hyper Text Markup Language\n*[CSS]: Cascading Style Sheets\n",
+        |opts| opts.ext_abbreviations = true,
+    );
+
+    html(
+        "*[HTML]: Hyper Text Markup Language\nHTML\n",
+        "<p>*[HTML]: Hyper Text Markup Language\nHTML</p>\n",
+    );
+}
+
 #[test]
 fn header_ids() {
     html_opts(
@@ -675,7 +1853,7 @@ fn footnote_in_table() {
         "</thead>\n",
         "<tbody>\n",
         "<tr>\n",
-        "<td>foot <sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup></td>\n",
+        "<td>foot <sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1-2\">1</a></sup></td>\n",
         "<td>note</td>\n",
         "</tr>\n",
         "</tbody>\n",
@@ -683,7 +1861,8 @@ fn footnote_in_table() {
         "<section class=\"footnotes\">\n",
         "<ol>\n",
         "<li id=\"fn1\">\n",
-        "<p>a footnote <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n",
+        "<p>a footnote <a href=\"#fnref1\" class=\"footnote-backref\">↩</a> ",
+        "<a href=\"#fnref1-2\" class=\"footnote-backref\">↩<sup class=\"footnote-ref\">2</sup></a></p>\n",
         "</li>\n",
         "</ol>\n",
         "</section>\n",
@@ -693,6 +1872,129 @@ fn footnote_in_table() {
     });
 }
 
+#[test]
+fn footnote_labels_and_inline_footnotes() {
+    use lint::Linter;
+
+    let input = concat!(
+        "One[^first] and two.^[An *inline* note.] Then one[^first] again[^missing].\n",
+        "\n",
+        "[^first]: The first.\n",
+        "\n",
+        "[^extra]: Never used.\n"
+    );
+
+    html_opts(
+        input,
+        concat!(
+            "<p>One<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup> and \
+             two.<sup class=\"footnote-ref\"><a href=\"#fn2\" id=\"fnref2\">2</a></sup> Then \
+             one<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1-2\">1</a></sup> \
+             again[^missing].</p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"fn1\">\n",
+            "<p>The first. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a> \
+             <a href=\"#fnref1-2\" class=\"footnote-backref\">↩<sup \
+             class=\"footnote-ref\">2</sup></a></p>\n",
+            "</li>\n",
+            "<li id=\"fn2\">\n",
+            "<p>An <em>inline</em> note. <a href=\"#fnref2\" \
+             class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n"
+        ),
+        |opts| {
+            opts.ext_footnotes = true;
+            opts.ext_inline_footnotes = true;
+        },
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_footnotes = true;
+    options.ext_inline_footnotes = true;
+    let root = parse_document(&arena, input, &options);
+
+    let mut output = vec![];
+    cm::format_document(root, &options, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        concat!(
+            "One[^first] and two.[^inline-1] Then one[^first] again[^missing].\n",
+            "\n",
+            "[^first]:\n",
+            "    The first.\n",
+            "\n",
+            "[^inline-1]:\n",
+            "    An *inline* note.\n",
+            "\n",
+            "[^extra]:\n",
+            "    Never used.\n"
+        )
+    );
+
+    let found = Linter::new(&options)
+        .lint(root, input)
+        .into_iter()
+        .map(|d| (d.rule, d.message))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        vec![
+            (
+                "missing-footnote",
+                "footnote [^missing] is not defined".to_string(),
+            ),
+            (
+                "unused-footnote",
+                "footnote [^extra] is never referenced".to_string(),
+            ),
+        ]
+    );
+
+    let root = parse_document(&arena, "A.^[Inline.] B[^inline-1].\n", &options);
+    let mut output = vec![];
+    cm::format_document(root, &options, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "A.[^inline-2] B[^inline-1].\n\n[^inline-2]:\n    Inline.\n"
+    );
+}
+
+#[test]
+fn footnote_html() {
+    html_opts(
+        concat!("A[^a] b[^a].\n", "\n", "[^a]: Note.\n"),
+        concat!(
+            "<p>A<sup class=\"footnote-ref\"><a href=\"#doc2-fn1\" id=\"doc2-fnref1\" \
+             aria-describedby=\"notes\">1</a></sup> b<sup class=\"footnote-ref\"><a \
+             href=\"#doc2-fn1\" id=\"doc2-fnref1-2\" aria-describedby=\"notes\">1</a></sup>.</p>\n",
+            "<ol>\n",
+            "<li id=\"doc2-fn1\">\n",
+            "<p>Note. <a href=\"#doc2-fnref1\" class=\"footnote-backref\" \
+             aria-label=\"Back to 1\"><span>^</span></a> <a href=\"#doc2-fnref1-2\" \
+             class=\"footnote-backref\" aria-label=\"Back to 1-2\"><span>^</span><sup \
+             class=\"footnote-ref\">2</sup></a></p>\n",
+            "</li>\n",
+            "</ol>\n"
+        ),
+        |opts| {
+            opts.ext_footnotes = true;
+            opts.ext_footnote_html = ::FootnoteHtml {
+                id_prefix: "doc2-".to_string(),
+                section_start: String::new(),
+                section_end: String::new(),
+                backref_content: "<span>^</span>".to_string(),
+                ref_attributes: "aria-describedby=\"notes\"".to_string(),
+                backref_attributes: "aria-label=\"Back to {}\"".to_string(),
+            };
+        },
+    );
+}
+
 #[test]
 fn regression_back_to_back_ranges() {
     html(
@@ -748,6 +2050,119 @@ fn smart_chars() {
     );
 }
 
+#[test]
+fn smart_quote_styles() {
+    use {QuoteStyle, SmartPunctuation};
+
+    let input = "\"Don't say 'never',\" she said -- twice...";
+    for &(quotes, expected) in &[
+        (
+            QuoteStyle::German,
+            "<p>„Don’t say ‚never‘,“ she said – twice…</p>\n",
+        ),
+        (
+            QuoteStyle::French,
+            "<p>«\u{202F}Don’t say ‹\u{202F}never\u{202F}›,\u{202F}» she said – twice…</p>\n",
+        ),
+        (
+            QuoteStyle::Japanese,
+            "<p>「Don’t say 『never』,」 she said – twice…</p>\n",
+        ),
+    ] {
+        html_opts(input, expected, |opts| {
+            opts.smart = true;
+            opts.smart_punctuation.quotes = quotes;
+        });
+    }
+
+    html_opts(
+        input,
+        "<p>“Don’t say ‘never’,” she said -- twice…</p>\n",
+        |opts| {
+            opts.smart = true;
+            opts.smart_punctuation = SmartPunctuation {
+                dashes: false,
+                ..SmartPunctuation::default()
+            };
+        },
+    );
+}
+
+#[test]
+fn typographic_replacements() {
+    html_opts(
+        concat!(
+            "# Acme(TM) -> 3/4 off\n",
+            "\n",
+            "(c) 2018, 11/2 +-1... see www.example.com/1/2 and `a->b`.\n",
+            "\n",
+            "Due 1/2/2020, 2/1/2, not 1/2, from a+-b@example.com.\n",
+            "\n",
+            "    (c) code\n"
+        ),
+        concat!(
+            "<h1>Acme™ → ¾ off</h1>\n",
+            "<p>© 2018, 11/2 ±1… see <a href=\"http://www.example.com/1/2\">www.example.com/1/2</a> \
+             and <code>a-&gt;b</code>.</p>\n",
+            "<p>Due 1/2/2020, 2/1/2, not ½, from \
+             <a href=\"mailto:a+-b@example.com\">a+-b@example.com</a>.</p>\n",
+            "<pre><code>(c) code\n",
+            "</code></pre>\n"
+        ),
+        |opts| {
+            opts.ext_replacements = ::typographic_replacements();
+            opts.ext_autolink = true;
+        },
+    );
+
+    html_opts(
+        "`x` <b>t</b> [a](u) **s**\n",
+        "<p><code>x</code> <b>t</b> <a href=\"u\">a</a> <strong>s</strong></p>\n",
+        |opts| {
+            opts.ext_replacements = [("`x", "Y"), ("<b", "Z"), ("[a", "W"), ("**", "V")]
+                .iter()
+                .map(|&(original, replacement)| (original.to_string(), replacement.to_string()))
+                .collect();
+        },
+    );
+
+    html_opts(
+        "Not \\(c), 1\\/2 or &#116;eh; 11/2.\n",
+        "<p>Not (c), 1/2 or teh; 11/2.</p>\n",
+        |opts| {
+            opts.ext_replacements = ::typographic_replacements();
+            opts.ext_replacements.push(("teh".to_string(), "the".to_string()));
+        },
+    );
+
+    commonmark_opts(
+        "Not \\(c), 1\\/2 or &#116;eh; 11/2.\n",
+        "Not \\(c), 1\\/2 or &#116;eh; 11/2.\n",
+        |opts| {
+            opts.ext_replacements = ::typographic_replacements();
+            opts.ext_replacements.push(("teh".to_string(), "the".to_string()));
+        },
+    );
+
+    html_opts(
+        "A -- B (c) C\n",
+        "<p>A — B (c) C</p>\n",
+        |opts| opts.ext_replacements = vec![("--".to_string(), "—".to_string())],
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.ext_replacements = ::typographic_replacements();
+    options.ext_replacements_original = true;
+    let root = parse_document(&arena, "Acme(tm) -> 1/2 off...\n", &options);
+    let mut output = vec![];
+    cm::format_document(root, &options, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Acme(tm) -> 1/2 off...\n"
+    );
+}
+
 #[test]
 fn nested_tables_1() {
     html_opts(
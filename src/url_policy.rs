@@ -0,0 +1,233 @@
+//! Policies applied to link and image URLs by the HTML formatter.
+
+/// Rules for the URLs of links and images in HTML output, set with the `url_policy` option.
+///
+/// The default policy changes nothing.
+///
+/// ```
+/// # use comrak::{markdown_to_html, ComrakOptions, UrlPolicy};
+/// let options = ComrakOptions {
+///   url_policy: Some(UrlPolicy {
+///     schemes: vec!["https".to_string()],
+///     image_hosts: vec!["img.example.com".to_string()],
+///     base_url: Some("https://example.com/docs/".to_string()),
+///     external_link_rel: Some("nofollow noopener".to_string()),
+///     external_link_target: Some("_blank".to_string()),
+///     image_proxy: Some("https://camo.example.com/?url={}".to_string()),
+///   }),
+///   ..ComrakOptions::default()
+/// };
+/// let input = "[a](guide.html) [b](https://elsewhere.org) [c](ftp://x.org)\n\n\
+///              ![d](https://img.example.com/d.png) ![e](https://x.org/e.png?s=1)\n";
+/// assert_eq!(markdown_to_html(input, &options),
+///            "<p><a href=\"https://example.com/docs/guide.html\">a</a> \
+///             <a href=\"https://elsewhere.org\" rel=\"nofollow noopener\" target=\"_blank\">b</a> \
+///             <a href=\"\">c</a></p>\n\
+///             <p><img src=\"https://img.example.com/d.png\" alt=\"d\" /> \
+///             <img src=\"https://camo.example.com/?url=https%3A%2F%2Fx.org%2Fe.png%3Fs%3D1\" alt=\"e\" /></p>\n");
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UrlPolicy {
+    /// The schemes allowed in link and image URLs, in lower case and without the trailing `:`.
+    /// Disallowed URLs are replaced with an empty string.  Relative URLs are always allowed.  If
+    /// empty, any scheme is allowed.
+    pub schemes: Vec<String>,
+
+    /// The hosts images may be loaded from directly.  Images from other hosts are loaded
+    /// through `image_proxy` if it is set, and are otherwise removed, as are `http`, `https`
+    /// and `ftp` URLs whose host can't be found.  If empty, images may be loaded from any host.
+    pub image_hosts: Vec<String>,
+
+    /// The URL relative URLs are resolved against.  Links to fragments of the same document,
+    /// such as `#intro`, are left relative.
+    pub base_url: Option<String>,
+
+    /// The `rel` attribute added to links to other hosts.  If `base_url` isn't set, every
+    /// absolute URL is considered external.
+    pub external_link_rel: Option<String>,
+
+    /// The `target` attribute added to links to other hosts.
+    pub external_link_target: Option<String>,
+
+    /// A URL template through which images from hosts other than `image_hosts` are loaded.
+    /// `{}` is replaced by the percent-encoded image URL.
+    pub image_proxy: Option<String>,
+}
+
+/// The parts of a URL the policy needs to examine.
+struct Parsed<'u> {
+    scheme: Option<&'u str>,
+    host: Option<&'u str>,
+}
+
+fn parse<'u>(url: &'u str) -> Parsed<'u> {
+    let scheme_end = url.find(&[':', '/', '?', '#'][..]);
+    let scheme = match scheme_end {
+        Some(i)
+            if i > 0
+                && url.as_bytes()[i] == b':'
+                && url.as_bytes()[0].is_ascii_alphabetic()
+                && url[..i]
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'-' || c == b'.') =>
+        {
+            Some(&url[..i])
+        }
+        _ => None,
+    };
+
+    let after_scheme = match scheme {
+        Some(s) => &url[s.len() + 1..],
+        None => url,
+    };
+    let host = if after_scheme.starts_with("//") {
+        let authority = &after_scheme[2..];
+        let end = authority
+            .find(&['/', '?', '#'][..])
+            .unwrap_or(authority.len());
+        let authority = &authority[..end];
+        let authority = authority.rsplit('@').next().unwrap_or(authority);
+        Some(authority.split(':').next().unwrap_or(authority))
+    } else {
+        None
+    };
+
+    Parsed {
+        scheme: scheme,
+        host: host,
+    }
+}
+
+impl<'u> Parsed<'u> {
+    /// Whether the URL has a scheme that browsers always find a host for, such as `https`, but
+    /// no host was found.  Browsers take `https:evil.com` to mean `https://evil.com`.
+    fn has_hidden_host(&self) -> bool {
+        self.host.is_none()
+            && self.scheme.map_or(false, |scheme| {
+                ["http", "https", "ftp"]
+                    .iter()
+                    .any(|s| s.eq_ignore_ascii_case(scheme))
+            })
+    }
+}
+
+impl UrlPolicy {
+    /// The URL to use for a link to `url`, or `None` if it isn't allowed.
+    pub fn link_url(&self, url: &str) -> Option<String> {
+        let url = self.resolve(url);
+        if self.scheme_allowed(&url) {
+            Some(url)
+        } else {
+            None
+        }
+    }
+
+    /// The URL to use for an image at `url`, or `None` if it isn't allowed.
+    pub fn image_url(&self, url: &str) -> Option<String> {
+        let url = self.resolve(url);
+        if !self.scheme_allowed(&url) {
+            return None;
+        }
+
+        let parsed = parse(&url);
+        let unrestricted = self.image_hosts.is_empty() && self.image_proxy.is_none();
+        let direct = match parsed.host {
+            None => unrestricted || !parsed.has_hidden_host(),
+            Some(host) => {
+                unrestricted || self.image_hosts.iter().any(|h| h.eq_ignore_ascii_case(host))
+            }
+        };
+
+        if direct {
+            Some(url)
+        } else {
+            self.image_proxy
+                .as_ref()
+                .map(|template| template.replace("{}", &percent_encode(&url)))
+        }
+    }
+
+    /// Whether a link to `url` is allowed, and leads to another host.
+    pub fn is_external(&self, url: &str) -> bool {
+        let url = self.resolve(url);
+        if !self.scheme_allowed(&url) {
+            return false;
+        }
+        let parsed = parse(&url);
+        let host = match parsed.host {
+            Some(host) => host,
+            None => return parsed.has_hidden_host(),
+        };
+        match self.base_url {
+            Some(ref base) => parse(base)
+                .host
+                .map_or(true, |base_host| !base_host.eq_ignore_ascii_case(host)),
+            None => true,
+        }
+    }
+
+    fn scheme_allowed(&self, url: &str) -> bool {
+        match parse(url).scheme {
+            None => true,
+            Some(scheme) => {
+                self.schemes.is_empty()
+                    || self.schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+            }
+        }
+    }
+
+    /// Resolves `url` against `base_url`, if set and `url` is relative.
+    fn resolve(&self, url: &str) -> String {
+        let base = match self.base_url {
+            Some(ref base) if !url.is_empty() && !url.starts_with('#') => base,
+            _ => return url.to_string(),
+        };
+
+        let parsed = parse(url);
+        if parsed.scheme.is_some() {
+            return url.to_string();
+        }
+
+        let base_parsed = parse(base);
+        let base_scheme = base_parsed.scheme.unwrap_or("https");
+        if url.starts_with("//") {
+            return format!("{}:{}", base_scheme, url);
+        }
+
+        let base = &base[..base.find('#').unwrap_or(base.len())];
+        // The length of the scheme and authority, e.g. `https://example.com`.
+        let mut origin_len = base_parsed.scheme.map_or(0, |s| s.len() + 1);
+        if base[origin_len..].starts_with("//") {
+            origin_len += 2;
+            origin_len += base[origin_len..]
+                .find(&['/', '?'][..])
+                .unwrap_or(base.len() - origin_len);
+        }
+
+        if url.starts_with('/') {
+            format!("{}{}", &base[..origin_len], url)
+        } else if url.starts_with('?') {
+            let path_end = base.find('?').unwrap_or(base.len());
+            format!("{}{}", &base[..path_end], url)
+        } else {
+            let path = &base[..base.find('?').unwrap_or(base.len())];
+            let dir = match path[origin_len..].rfind('/') {
+                Some(i) => &path[..origin_len + i + 1],
+                None => return format!("{}/{}", path, url),
+            };
+            format!("{}{}", dir, url)
+        }
+    }
+}
+
+fn percent_encode(url: &str) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for &c in url.as_bytes() {
+        if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b'_' || c == b'~' {
+            encoded.push(c as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", c));
+        }
+    }
+    encoded
+}
@@ -0,0 +1,756 @@
+//! Visitors and transformers over the AST.
+//!
+//! A `Visitor` looks at each node of a tree in document order, and a `Transformer` may also
+//! change them.  Both have `enter` and `exit` hooks called for every node, and a method per
+//! node type called after `enter`, with the node's value.
+//!
+//! ```
+//! # use comrak::{format_html, parse_document, Arena, ComrakOptions};
+//! # use comrak::nodes::{AstNode, NodeHeading, NodeLink};
+//! use comrak::visit::{transform, walk, Transformer, Visitor, Walk};
+//!
+//! struct Images(Vec<String>);
+//!
+//! impl<'a> Visitor<'a> for Images {
+//!     fn visit_image(&mut self, _node: &'a AstNode<'a>, link: &NodeLink) -> Walk {
+//!         self.0.push(String::from_utf8(link.url.clone()).unwrap());
+//!         Walk::SkipChildren
+//!     }
+//! }
+//!
+//! struct Demote;
+//!
+//! impl<'a> Transformer<'a> for Demote {
+//!     fn transform_heading(&mut self, _node: &'a AstNode<'a>, heading: &mut NodeHeading) -> Walk {
+//!         heading.level = (heading.level + 1).min(6);
+//!         Walk::Continue
+//!     }
+//! }
+//!
+//! let arena = Arena::new();
+//! let root = parse_document(&arena, "# ![a](a.png)\n\n## ![b](b.png)\n", &ComrakOptions::default());
+//!
+//! let mut images = Images(vec![]);
+//! walk(root, &mut images);
+//! assert_eq!(images.0, vec!["a.png", "b.png"]);
+//!
+//! transform(root, &mut Demote);
+//! let mut html = vec![];
+//! format_html(root, &ComrakOptions::default(), &mut html).unwrap();
+//! assert_eq!(String::from_utf8(html).unwrap(),
+//!            "<h2><img src=\"a.png\" alt=\"a\" /></h2>\n\
+//!             <h3><img src=\"b.png\" alt=\"b\" /></h3>\n");
+//! ```
+
+use arena_tree::NodeEdge;
+use nodes::{
+    AstNode, NodeAbbreviation, NodeCodeBlock, NodeContainer, NodeDescriptionItem,
+    NodeFootnoteDefinition, NodeFootnoteReference, NodeHeading, NodeHtmlBlock, NodeLink, NodeList,
+    NodeReplacement, NodeTaskItem, NodeValue, TableAlignment,
+};
+
+/// What to do after entering a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+    /// Carry on into the node's children.
+    Continue,
+
+    /// Skip the node's children; its `exit` hook is still called.
+    SkipChildren,
+
+    /// Stop walking immediately.
+    Stop,
+}
+
+/// Looks at the nodes of a tree, without changing them.  Every method does nothing by default.
+///
+/// The node's value is borrowed while the per-type methods are called, so they must not
+/// borrow it mutably.
+pub trait Visitor<'a> {
+    /// Called on entering every node, before the method for its type.  If this returns anything
+    /// other than `Walk::Continue`, the method for its type isn't called.
+    fn enter(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on leaving every node, after its children.
+    fn exit(&mut self, _node: &'a AstNode<'a>) {}
+
+    /// Called on entering a `Document` node.
+    fn visit_document(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `BlockQuote` node.
+    fn visit_block_quote(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `List` node.
+    fn visit_list(&mut self, _node: &'a AstNode<'a>, _list: &NodeList) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Item` node.
+    fn visit_item(&mut self, _node: &'a AstNode<'a>, _list: &NodeList) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionList` node.
+    fn visit_description_list(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionItem` node.
+    fn visit_description_item(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _item: &NodeDescriptionItem,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionTerm` node.
+    fn visit_description_term(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionDetails` node.
+    fn visit_description_details(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `CodeBlock` node.
+    fn visit_code_block(&mut self, _node: &'a AstNode<'a>, _code_block: &NodeCodeBlock) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `HtmlBlock` node.
+    fn visit_html_block(&mut self, _node: &'a AstNode<'a>, _html_block: &NodeHtmlBlock) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Paragraph` node.
+    fn visit_paragraph(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Heading` node.
+    fn visit_heading(&mut self, _node: &'a AstNode<'a>, _heading: &NodeHeading) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `ThematicBreak` node.
+    fn visit_thematic_break(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `FootnoteDefinition` node.
+    fn visit_footnote_definition(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _footnote_definition: &NodeFootnoteDefinition,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Table` node.
+    fn visit_table(&mut self, _node: &'a AstNode<'a>, _alignments: &[TableAlignment]) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TableRow` node.
+    fn visit_table_row(&mut self, _node: &'a AstNode<'a>, _header: bool) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TableCell` node.
+    fn visit_table_cell(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `SpoilerBlock` node.
+    fn visit_spoiler_block(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Container` node.
+    fn visit_container(&mut self, _node: &'a AstNode<'a>, _container: &NodeContainer) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Text` node.
+    fn visit_text(&mut self, _node: &'a AstNode<'a>, _text: &[u8]) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `SoftBreak` node.
+    fn visit_softbreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `LineBreak` node.
+    fn visit_linebreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Code` node.
+    fn visit_code(&mut self, _node: &'a AstNode<'a>, _literal: &[u8]) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `HtmlInline` node.
+    fn visit_html_inline(&mut self, _node: &'a AstNode<'a>, _literal: &[u8]) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Emph` node.
+    fn visit_emph(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Strong` node.
+    fn visit_strong(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Strikethrough` node.
+    fn visit_strikethrough(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Superscript` node.
+    fn visit_superscript(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Subscript` node.
+    fn visit_subscript(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Highlight` node.
+    fn visit_highlight(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Insert` node.
+    fn visit_insert(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Underline` node.
+    fn visit_underline(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Spoiler` node.
+    fn visit_spoiler(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Link` node.
+    fn visit_link(&mut self, _node: &'a AstNode<'a>, _link: &NodeLink) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Image` node.
+    fn visit_image(&mut self, _node: &'a AstNode<'a>, _link: &NodeLink) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `FootnoteReference` node.
+    fn visit_footnote_reference(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _footnote_reference: &NodeFootnoteReference,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TaskItem` node.
+    fn visit_task_item(&mut self, _node: &'a AstNode<'a>, _task_item: &NodeTaskItem) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Replacement` node.
+    fn visit_replacement(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _replacement: &NodeReplacement,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Abbreviation` node.
+    fn visit_abbreviation(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _abbreviation: &NodeAbbreviation,
+    ) -> Walk {
+        Walk::Continue
+    }
+}
+
+/// Changes the nodes of a tree.  Every method does nothing by default.
+///
+/// The per-type methods may change the node's value, and `enter` may change the node's
+/// children.  The per-type methods are given a copy of the node's value, which replaces the
+/// node's own if changed, so the node may be read while they run.  Any hook may detach or
+/// replace the node, or insert siblings after it, which aren't visited; a node detached on
+/// entering has neither its children nor `exit` visited.
+pub trait Transformer<'a> {
+    /// Called on entering every node, before the method for its type.  If this returns anything
+    /// other than `Walk::Continue`, the method for its type isn't called.
+    fn enter(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on leaving every node, after its children.
+    fn exit(&mut self, _node: &'a AstNode<'a>) {}
+
+    /// Called on entering a `Document` node.
+    fn transform_document(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `BlockQuote` node.
+    fn transform_block_quote(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `List` node.
+    fn transform_list(&mut self, _node: &'a AstNode<'a>, _list: &mut NodeList) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Item` node.
+    fn transform_item(&mut self, _node: &'a AstNode<'a>, _list: &mut NodeList) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionList` node.
+    fn transform_description_list(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionItem` node.
+    fn transform_description_item(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _item: &mut NodeDescriptionItem,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionTerm` node.
+    fn transform_description_term(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `DescriptionDetails` node.
+    fn transform_description_details(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `CodeBlock` node.
+    fn transform_code_block(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _code_block: &mut NodeCodeBlock,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `HtmlBlock` node.
+    fn transform_html_block(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _html_block: &mut NodeHtmlBlock,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Paragraph` node.
+    fn transform_paragraph(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Heading` node.
+    fn transform_heading(&mut self, _node: &'a AstNode<'a>, _heading: &mut NodeHeading) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `ThematicBreak` node.
+    fn transform_thematic_break(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `FootnoteDefinition` node.
+    fn transform_footnote_definition(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _footnote_definition: &mut NodeFootnoteDefinition,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Table` node.
+    fn transform_table(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _alignments: &mut Vec<TableAlignment>,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TableRow` node.
+    fn transform_table_row(&mut self, _node: &'a AstNode<'a>, _header: &mut bool) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TableCell` node.
+    fn transform_table_cell(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `SpoilerBlock` node.
+    fn transform_spoiler_block(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Container` node.
+    fn transform_container(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _container: &mut NodeContainer,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Text` node.
+    fn transform_text(&mut self, _node: &'a AstNode<'a>, _text: &mut Vec<u8>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `SoftBreak` node.
+    fn transform_softbreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `LineBreak` node.
+    fn transform_linebreak(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Code` node.
+    fn transform_code(&mut self, _node: &'a AstNode<'a>, _literal: &mut Vec<u8>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `HtmlInline` node.
+    fn transform_html_inline(&mut self, _node: &'a AstNode<'a>, _literal: &mut Vec<u8>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Emph` node.
+    fn transform_emph(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Strong` node.
+    fn transform_strong(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Strikethrough` node.
+    fn transform_strikethrough(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Superscript` node.
+    fn transform_superscript(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Subscript` node.
+    fn transform_subscript(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Highlight` node.
+    fn transform_highlight(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Insert` node.
+    fn transform_insert(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Underline` node.
+    fn transform_underline(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Spoiler` node.
+    fn transform_spoiler(&mut self, _node: &'a AstNode<'a>) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Link` node.
+    fn transform_link(&mut self, _node: &'a AstNode<'a>, _link: &mut NodeLink) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Image` node.
+    fn transform_image(&mut self, _node: &'a AstNode<'a>, _link: &mut NodeLink) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `FootnoteReference` node.
+    fn transform_footnote_reference(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _footnote_reference: &mut NodeFootnoteReference,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `TaskItem` node.
+    fn transform_task_item(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _task_item: &mut NodeTaskItem,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering a `Replacement` node.
+    fn transform_replacement(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _replacement: &mut NodeReplacement,
+    ) -> Walk {
+        Walk::Continue
+    }
+
+    /// Called on entering an `Abbreviation` node.
+    fn transform_abbreviation(
+        &mut self,
+        _node: &'a AstNode<'a>,
+        _abbreviation: &mut NodeAbbreviation,
+    ) -> Walk {
+        Walk::Continue
+    }
+}
+
+/// Walks the tree rooted at `root` in document order with `visitor`.
+pub fn walk<'a, V: Visitor<'a>>(root: &'a AstNode<'a>, visitor: &mut V) {
+    let mut edges = root.traverse();
+    while let Some(edge) = edges.next() {
+        match edge {
+            NodeEdge::Start(node) => {
+                let mut result = visitor.enter(node);
+                if result == Walk::Continue {
+                    result = visit_value(visitor, node);
+                }
+                match result {
+                    Walk::Continue => (),
+                    Walk::SkipChildren => {
+                        for edge in &mut edges {
+                            if let NodeEdge::End(n) = edge {
+                                if n.same_node(node) {
+                                    break;
+                                }
+                            }
+                        }
+                        visitor.exit(node);
+                    }
+                    Walk::Stop => return,
+                }
+            }
+            NodeEdge::End(node) => visitor.exit(node),
+        }
+    }
+}
+
+/// Walks the tree rooted at `root` in document order with `transformer`.
+pub fn transform<'a, T: Transformer<'a>>(root: &'a AstNode<'a>, transformer: &mut T) {
+    // `Node::traverse` finds the next node from the current one only when asked, which goes
+    // wrong if the current one has just been detached, so the next node is found before any
+    // hook is called.
+    let mut next = Some(NodeEdge::Start(root));
+    while let Some(edge) = next {
+        next = match edge {
+            NodeEdge::Start(node) => {
+                let after = next_after(root, node);
+                let parent = node.parent();
+
+                let mut result = transformer.enter(node);
+                if result == Walk::Continue && !moved(root, node, parent) {
+                    result = transform_value(transformer, node);
+                }
+
+                if moved(root, node, parent) {
+                    // The node's children and `exit` go with it.
+                    after
+                } else {
+                    match result {
+                        Walk::Continue => match node.first_child() {
+                            Some(child) => Some(NodeEdge::Start(child)),
+                            None => Some(NodeEdge::End(node)),
+                        },
+                        Walk::SkipChildren => Some(NodeEdge::End(node)),
+                        Walk::Stop => return,
+                    }
+                }
+            }
+            NodeEdge::End(node) => {
+                let after = next_after(root, node);
+                transformer.exit(node);
+                after
+            }
+        };
+    }
+}
+
+/// The edge after `node`'s end: the start of its next sibling, or else its parent's end.
+fn next_after<'a>(
+    root: &'a AstNode<'a>,
+    node: &'a AstNode<'a>,
+) -> Option<NodeEdge<&'a AstNode<'a>>> {
+    if node.same_node(root) {
+        None
+    } else if let Some(sibling) = node.next_sibling() {
+        Some(NodeEdge::Start(sibling))
+    } else {
+        node.parent().map(NodeEdge::End)
+    }
+}
+
+/// Whether `node` has been detached or moved from under `parent` by a hook.
+fn moved<'a>(
+    root: &'a AstNode<'a>,
+    node: &'a AstNode<'a>,
+    parent: Option<&'a AstNode<'a>>,
+) -> bool {
+    match (node.parent(), parent) {
+        (Some(now), Some(before)) => !now.same_node(before),
+        (None, None) => false,
+        _ => !node.same_node(root),
+    }
+}
+
+fn visit_value<'a, V: Visitor<'a>>(visitor: &mut V, node: &'a AstNode<'a>) -> Walk {
+    match node.data.borrow().value {
+        NodeValue::Document => visitor.visit_document(node),
+        NodeValue::BlockQuote => visitor.visit_block_quote(node),
+        NodeValue::List(ref list) => visitor.visit_list(node, list),
+        NodeValue::Item(ref list) => visitor.visit_item(node, list),
+        NodeValue::DescriptionList => visitor.visit_description_list(node),
+        NodeValue::DescriptionItem(ref item) => visitor.visit_description_item(node, item),
+        NodeValue::DescriptionTerm => visitor.visit_description_term(node),
+        NodeValue::DescriptionDetails => visitor.visit_description_details(node),
+        NodeValue::CodeBlock(ref code_block) => visitor.visit_code_block(node, code_block),
+        NodeValue::HtmlBlock(ref html_block) => visitor.visit_html_block(node, html_block),
+        NodeValue::Paragraph => visitor.visit_paragraph(node),
+        NodeValue::Heading(ref heading) => visitor.visit_heading(node, heading),
+        NodeValue::ThematicBreak => visitor.visit_thematic_break(node),
+        NodeValue::FootnoteDefinition(ref nfd) => visitor.visit_footnote_definition(node, nfd),
+        NodeValue::Table(ref alignments) => visitor.visit_table(node, alignments),
+        NodeValue::TableRow(header) => visitor.visit_table_row(node, header),
+        NodeValue::TableCell => visitor.visit_table_cell(node),
+        NodeValue::SpoilerBlock => visitor.visit_spoiler_block(node),
+        NodeValue::Container(ref container) => visitor.visit_container(node, container),
+        NodeValue::Text(ref text) => visitor.visit_text(node, text),
+        NodeValue::SoftBreak => visitor.visit_softbreak(node),
+        NodeValue::LineBreak => visitor.visit_linebreak(node),
+        NodeValue::Code(ref literal) => visitor.visit_code(node, literal),
+        NodeValue::HtmlInline(ref literal) => visitor.visit_html_inline(node, literal),
+        NodeValue::Emph => visitor.visit_emph(node),
+        NodeValue::Strong => visitor.visit_strong(node),
+        NodeValue::Strikethrough => visitor.visit_strikethrough(node),
+        NodeValue::Superscript => visitor.visit_superscript(node),
+        NodeValue::Subscript => visitor.visit_subscript(node),
+        NodeValue::Highlight => visitor.visit_highlight(node),
+        NodeValue::Insert => visitor.visit_insert(node),
+        NodeValue::Underline => visitor.visit_underline(node),
+        NodeValue::Spoiler => visitor.visit_spoiler(node),
+        NodeValue::Link(ref link) => visitor.visit_link(node, link),
+        NodeValue::Image(ref link) => visitor.visit_image(node, link),
+        NodeValue::FootnoteReference(ref nfr) => visitor.visit_footnote_reference(node, nfr),
+        NodeValue::TaskItem(ref task_item) => visitor.visit_task_item(node, task_item),
+        NodeValue::Replacement(ref nr) => visitor.visit_replacement(node, nr),
+        NodeValue::Abbreviation(ref na) => visitor.visit_abbreviation(node, na),
+    }
+}
+
+fn transform_value<'a, T: Transformer<'a>>(transformer: &mut T, node: &'a AstNode<'a>) -> Walk {
+    // The method is given a copy of the value, so that it may borrow the node itself.
+    let original = node.data.borrow().value.clone();
+    let mut value = original.clone();
+    let result = transform_value_of(transformer, node, &mut value);
+    if value != original {
+        node.data.borrow_mut().value = value;
+    }
+    result
+}
+
+fn transform_value_of<'a, T: Transformer<'a>>(
+    transformer: &mut T,
+    node: &'a AstNode<'a>,
+    value: &mut NodeValue,
+) -> Walk {
+    match *value {
+        NodeValue::Document => transformer.transform_document(node),
+        NodeValue::BlockQuote => transformer.transform_block_quote(node),
+        NodeValue::List(ref mut list) => transformer.transform_list(node, list),
+        NodeValue::Item(ref mut list) => transformer.transform_item(node, list),
+        NodeValue::DescriptionList => transformer.transform_description_list(node),
+        NodeValue::DescriptionItem(ref mut item) => {
+            transformer.transform_description_item(node, item)
+        }
+        NodeValue::DescriptionTerm => transformer.transform_description_term(node),
+        NodeValue::DescriptionDetails => transformer.transform_description_details(node),
+        NodeValue::CodeBlock(ref mut code_block) => {
+            transformer.transform_code_block(node, code_block)
+        }
+        NodeValue::HtmlBlock(ref mut html_block) => {
+            transformer.transform_html_block(node, html_block)
+        }
+        NodeValue::Paragraph => transformer.transform_paragraph(node),
+        NodeValue::Heading(ref mut heading) => transformer.transform_heading(node, heading),
+        NodeValue::ThematicBreak => transformer.transform_thematic_break(node),
+        NodeValue::FootnoteDefinition(ref mut nfd) => {
+            transformer.transform_footnote_definition(node, nfd)
+        }
+        NodeValue::Table(ref mut alignments) => transformer.transform_table(node, alignments),
+        NodeValue::TableRow(ref mut header) => transformer.transform_table_row(node, header),
+        NodeValue::TableCell => transformer.transform_table_cell(node),
+        NodeValue::SpoilerBlock => transformer.transform_spoiler_block(node),
+        NodeValue::Container(ref mut container) => transformer.transform_container(node, container),
+        NodeValue::Text(ref mut text) => transformer.transform_text(node, text),
+        NodeValue::SoftBreak => transformer.transform_softbreak(node),
+        NodeValue::LineBreak => transformer.transform_linebreak(node),
+        NodeValue::Code(ref mut literal) => transformer.transform_code(node, literal),
+        NodeValue::HtmlInline(ref mut literal) => transformer.transform_html_inline(node, literal),
+        NodeValue::Emph => transformer.transform_emph(node),
+        NodeValue::Strong => transformer.transform_strong(node),
+        NodeValue::Strikethrough => transformer.transform_strikethrough(node),
+        NodeValue::Superscript => transformer.transform_superscript(node),
+        NodeValue::Subscript => transformer.transform_subscript(node),
+        NodeValue::Highlight => transformer.transform_highlight(node),
+        NodeValue::Insert => transformer.transform_insert(node),
+        NodeValue::Underline => transformer.transform_underline(node),
+        NodeValue::Spoiler => transformer.transform_spoiler(node),
+        NodeValue::Link(ref mut link) => transformer.transform_link(node, link),
+        NodeValue::Image(ref mut link) => transformer.transform_image(node, link),
+        NodeValue::FootnoteReference(ref mut nfr) => {
+            transformer.transform_footnote_reference(node, nfr)
+        }
+        NodeValue::TaskItem(ref mut task_item) => transformer.transform_task_item(node, task_item),
+        NodeValue::Replacement(ref mut nr) => transformer.transform_replacement(node, nr),
+        NodeValue::Abbreviation(ref mut na) => transformer.transform_abbreviation(node, na),
+    }
+}